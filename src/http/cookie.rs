@@ -0,0 +1,259 @@
+// Cookie parsing and Set-Cookie serialization
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A cookie to be sent with `Response::set_cookie`.
+/// Its `Display` implementation produces the value of the `Set-Cookie` header.
+///
+/// # Example
+///
+///
+/// let cookie = Cookie::new("theme", "dark")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// res.set_cookie(cookie);
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    expires: Option<SystemTime>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Like `try_new`, for names and values known to be valid
+    ///
+    /// # Panics
+    ///
+    /// If the name or the value is invalid
+    pub fn new(name: &str, value: &str) -> Self {
+        Cookie::try_new(name, value).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Fails if the name is not a token or the value holds characters not allowed
+    /// in a cookie (whitespace, control characters, `"`, `,`, `;` or `\`), which
+    /// would let it add attributes or headers to the response.
+    pub fn try_new(name: &str, value: &str) -> Result<Self, Error> {
+        if !valid_name(name) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid cookie name {:?}", name)));
+        }
+        check_value(name, value)?;
+        Ok(Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            expires: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        })
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        assert!(valid_attribute(path), "Invalid cookie path {:?}", path);
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        assert!(valid_attribute(domain), "Invalid cookie domain {:?}", domain);
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// `SameSite=None` also requires the cookie to be `Secure`
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_value(&self) -> &str {
+        &self.value
+    }
+
+    /// Panics like `new` on invalid values
+    pub fn set_value(&mut self, value: &str) {
+        self.try_set_value(value).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Fails like `try_new` on invalid values
+    pub fn try_set_value(&mut self, value: &str) -> Result<(), Error> {
+        check_value(&self.name, value)?;
+        self.value = value.to_string();
+        Ok(())
+    }
+
+    // Cookie that makes the browser drop a previously set cookie
+    pub(crate) fn removal(name: &str) -> Self {
+        Cookie::new(name, "")
+            .path("/")
+            .expires(SystemTime::UNIX_EPOCH)
+            .max_age(Duration::ZERO)
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", httpdate::fmt_http_date(expires))?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+        Ok(())
+    }
+}
+
+// Token of RFC 7230, as required by RFC 6265 for the name
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+// Only the cookie-octet characters of RFC 6265
+fn check_value(name: &str, value: &str) -> Result<(), Error> {
+    match value.chars().all(|c| c.is_ascii_graphic() && !"\",;\\".contains(c)) {
+        true => Ok(()),
+        false => Err(Error::new(ErrorKind::InvalidInput, format!("Invalid value for cookie {}: {:?}", name, value))),
+    }
+}
+
+// Path and Domain end at the next `;`
+fn valid_attribute(value: &str) -> bool {
+    value.chars().all(|c| !c.is_ascii_control() && c != ';')
+}
+
+/// Parse the value of a `Cookie` request header (`name=value; other=value`)
+pub(crate) fn parse_cookie_header(header: &str) -> HashMap<String, String> {
+    let mut cookies = HashMap::new();
+    for pair in header.split(';') {
+        let Some((name, value)) = pair.split_once('=') else {
+            continue;
+        };
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        let value = value.trim();
+        let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+        // The first occurrence is the most specific one
+        cookies.entry(name.to_string()).or_insert(value.to_string());
+    }
+    cookies
+}
+
+
+#[cfg(test)]
+mod test_cookie {
+    use std::time::{Duration, SystemTime};
+
+    use super::{parse_cookie_header, Cookie, SameSite};
+
+    #[test]
+    fn set_cookie_header() {
+        let cookie = Cookie::new("id", "a3fWa")
+            .path("/")
+            .domain("example.com")
+            .expires(SystemTime::UNIX_EPOCH + Duration::from_secs(1445412480))
+            .max_age(Duration::from_secs(3600))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Strict);
+        assert_eq!(cookie.to_string(), "id=a3fWa; Path=/; Domain=example.com; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=3600; Secure; HttpOnly; SameSite=Strict");
+        assert_eq!(Cookie::new("a", "b").to_string(), "a=b");
+    }
+
+    #[test]
+    fn parse_header() {
+        let cookies = parse_cookie_header("theme=dark; id=\"42\";  empty=; broken; theme=light");
+        assert_eq!(cookies.get("theme").unwrap(), "dark");
+        assert_eq!(cookies.get("id").unwrap(), "42");
+        assert_eq!(cookies.get("empty").unwrap(), "");
+        assert!(!cookies.contains_key("broken"));
+    }
+
+    #[test]
+    fn injection_is_rejected() {
+        for value in ["a; Domain=evil.com", "a\r\nSet-Cookie: admin=1", "a,b", "a b", "\"a\""] {
+            assert!(Cookie::try_new("id", value).is_err(), "{:?}", value);
+            assert!(std::panic::catch_unwind(|| Cookie::new("id", value)).is_err(), "{:?}", value);
+        }
+        assert!(std::panic::catch_unwind(|| Cookie::new("a=b", "c")).is_err());
+        assert!(std::panic::catch_unwind(|| Cookie::new("id", "a").path("/; Secure")).is_err());
+        assert!(std::panic::catch_unwind(|| Cookie::new("id", "a").set_value("a;b")).is_err());
+        assert_eq!(Cookie::new("id", "eyJ0+/=").to_string(), "id=eyJ0+/=");
+
+        let mut res = crate::http::response::Response::empty();
+        assert!(res.try_set_header("Location", "/\r\nSet-Cookie: admin=1").is_err());
+        assert!(res.try_set_header("Bad Name", "x").is_err());
+        assert!(res.try_set_header("Location", "/home").is_ok());
+        assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            res.set_header("Location", "/\r\nSet-Cookie: admin=1");
+        })).is_err());
+    }
+}
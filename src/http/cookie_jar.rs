@@ -0,0 +1,234 @@
+// Signed and encrypted cookies with key rotation
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::http::cookie::Cookie;
+use crate::http::request::Request;
+use crate::http::response::Response;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const MIN_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+// Signing and encryption keys derived from one master key
+struct Keys {
+    signing: [u8; 32],
+    encryption: [u8; 32],
+}
+
+impl Keys {
+    fn derive(master: &[u8]) -> Self {
+        assert!(master.len() >= MIN_KEY_LEN, "Cookie keys must be at least {} bytes long", MIN_KEY_LEN);
+        let derive = |purpose: &[u8]| -> [u8; 32] {
+            let mut mac = <HmacSha256 as Mac>::new_from_slice(master).expect("HMAC accepts any key length");
+            mac.update(purpose);
+            mac.finalize().into_bytes().into()
+        };
+        Keys {
+            signing: derive(b"myapi-cookie-signing"),
+            encryption: derive(b"myapi-cookie-encryption"),
+        }
+    }
+
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.signing).expect("HMAC accepts any key length");
+        // The name is authenticated too, so a value can't be moved to another cookie
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&self.encryption))
+    }
+}
+
+/// Signs (HMAC-SHA256) or encrypts (AES-256-GCM) cookie values.
+///
+/// New cookies are always protected with the current key. Keys added with
+/// `previous_key` are only used to read cookies issued before a key rotation.
+/// Tampered cookies, or cookies protected with an unknown key, are read as `None`.
+///
+/// # Example
+///
+///
+/// let jar = CookieJar::new(b"a master key that is at least 32 bytes long")
+///     .previous_key(b"the master key we used before the rotation.");
+///
+/// jar.set_signed(res, Cookie::new("user", "42").http_only(true));
+/// let user = jar.signed(&req, "user");
+///
+/// jar.set_private(res, "cart", r#"{"items": ["green tea"]}"#);
+/// let cart = jar.private(&req, "cart");
+///
+pub struct CookieJar {
+    keys: Vec<Keys>,
+}
+
+impl CookieJar {
+
+    /// Create a jar with the current master key (at least 32 random bytes)
+    pub fn new(key: &[u8]) -> Self {
+        CookieJar { keys: vec![Keys::derive(key)] }
+    }
+
+    /// Also accept cookies protected with an older master key
+    pub fn previous_key(mut self, key: &[u8]) -> Self {
+        self.keys.push(Keys::derive(key));
+        self
+    }
+
+    fn current(&self) -> &Keys {
+        &self.keys[0]
+    }
+
+    /// Protect the value of the cookie with a signature.
+    /// The value stays readable by the client but can't be modified.
+    pub fn sign(&self, mut cookie: Cookie) -> Cookie {
+        let tag = self.current().mac(cookie.get_name(), cookie.get_value()).finalize().into_bytes();
+        let value = format!("{}.{}", URL_SAFE_NO_PAD.encode(tag), cookie.get_value());
+        cookie.set_value(&value);
+        cookie
+    }
+
+    /// Check the signature of a signed value and return the original value
+    pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (tag, value) = signed.split_once('.')?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+        self.keys.iter()
+            .any(|keys| keys.mac(name, value).verify_slice(&tag).is_ok())
+            .then(|| value.to_string())
+    }
+
+    /// Cookie holding the encrypted value, which can be neither read nor modified
+    /// by the client. The value may be any text (JSON, spaces, quotes...), only the
+    /// ciphertext has to fit in a cookie. Set the other attributes on the result.
+    ///
+    /// # Panics
+    ///
+    /// If the name is not a valid cookie name
+    pub fn encrypt(&self, name: &str, value: &str) -> Cookie {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload { msg: value.as_bytes(), aad: name.as_bytes() };
+        let ciphertext = self.current().cipher().encrypt(&nonce, payload).expect("Error encrypting cookie");
+
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        Cookie::new(name, &URL_SAFE_NO_PAD.encode(data))
+    }
+
+    /// Decrypt an encrypted value and return the original value
+    pub fn decrypt(&self, name: &str, encrypted: &str) -> Option<String> {
+        let data = URL_SAFE_NO_PAD.decode(encrypted).ok()?;
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::from_slice(nonce);
+        self.keys.iter().find_map(|keys| {
+            let payload = Payload { msg: ciphertext, aad: name.as_bytes() };
+            let plain = keys.cipher().decrypt(nonce, payload).ok()?;
+            String::from_utf8(plain).ok()
+        })
+    }
+
+    /// Value of a signed cookie sent by the client, `None` if missing or tampered
+    pub fn signed(&self, req: &Request, name: &str) -> Option<String> {
+        self.verify(name, &req.cookie(name)?)
+    }
+
+    /// Value of an encrypted cookie sent by the client, `None` if missing or tampered
+    pub fn private(&self, req: &Request, name: &str) -> Option<String> {
+        self.decrypt(name, &req.cookie(name)?)
+    }
+
+    /// Sign the cookie and add it to the response
+    pub fn set_signed<'a>(&self, res: &'a mut Response, cookie: Cookie) -> &'a mut Response {
+        res.set_cookie(self.sign(cookie))
+    }
+
+    /// Add an encrypted cookie to the response, with `Path=/` and `HttpOnly`.
+    /// Use `encrypt` and `Response::set_cookie` for other attributes.
+    pub fn set_private<'a>(&self, res: &'a mut Response, name: &str, value: &str) -> &'a mut Response {
+        res.set_cookie(self.encrypt(name, value).path("/").http_only(true))
+    }
+}
+
+
+#[cfg(test)]
+mod test_cookie_jar {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    use super::CookieJar;
+    use crate::http::cookie::Cookie;
+    use crate::http::response::Response;
+
+    const OLD_KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+    const NEW_KEY: &[u8] = b"fedcba9876543210fedcba9876543210";
+
+    #[test]
+    fn signed_cookies() {
+        let jar = CookieJar::new(OLD_KEY);
+        let cookie = jar.sign(Cookie::new("user", "42"));
+        assert!(cookie.get_value().ends_with(".42"));
+        assert_eq!(jar.verify("user", cookie.get_value()), Some("42".to_string()));
+
+        let tampered = cookie.get_value().replace(".42", ".43");
+        assert_eq!(jar.verify("user", &tampered), None);
+        assert_eq!(jar.verify("user", "42"), None);
+
+        // Cookies signed before a rotation are still accepted
+        let rotated = CookieJar::new(NEW_KEY).previous_key(OLD_KEY);
+        assert_eq!(rotated.verify("user", cookie.get_value()), Some("42".to_string()));
+        assert_eq!(CookieJar::new(NEW_KEY).verify("user", cookie.get_value()), None);
+    }
+
+    #[test]
+    fn encrypted_cookies() {
+        let jar = CookieJar::new(OLD_KEY);
+        let cookie = jar.encrypt("cart", "apples=3");
+        assert!(!cookie.get_value().contains("apples"));
+        assert_eq!(jar.decrypt("cart", cookie.get_value()), Some("apples=3".to_string()));
+
+        // A byte changed in the middle of the ciphertext
+        let mut data = URL_SAFE_NO_PAD.decode(cookie.get_value()).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 0x01;
+        assert_eq!(jar.decrypt("cart", &URL_SAFE_NO_PAD.encode(data)), None);
+
+        let rotated = CookieJar::new(NEW_KEY).previous_key(OLD_KEY);
+        assert_eq!(rotated.decrypt("cart", cookie.get_value()), Some("apples=3".to_string()));
+    }
+
+    #[test]
+    fn any_text_can_be_encrypted() {
+        let jar = CookieJar::new(OLD_KEY);
+        let value = r#"{"items": ["green tea", "a \"special\" cup"]}; more"#;
+        let mut res = Response::empty();
+        jar.set_private(&mut res, "cart", value);
+
+        let header = res.get_header("Set-Cookie").unwrap();
+        assert!(header.ends_with("; Path=/; HttpOnly"));
+        let (_, encrypted) = header.split_once(';').unwrap().0.split_once('=').unwrap();
+        assert_eq!(jar.decrypt("cart", encrypted), Some(value.to_string()));
+    }
+
+    #[test]
+    fn values_are_bound_to_their_name() {
+        let jar = CookieJar::new(OLD_KEY);
+        let signed = jar.sign(Cookie::new("user", "42"));
+        assert_eq!(jar.verify("admin", signed.get_value()), None);
+
+        let encrypted = jar.encrypt("user", "42");
+        assert_eq!(jar.decrypt("admin", encrypted.get_value()), None);
+        assert_eq!(jar.decrypt("user", encrypted.get_value()), Some("42".to_string()));
+    }
+}
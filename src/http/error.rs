@@ -0,0 +1,176 @@
+// Framework error type for fallible handlers
+
+use std::fmt;
+
+use crate::http::into_response::IntoResponse;
+use crate::http::{response::Response, status::HttpStatus};
+
+/// An error that maps to an HTTP status.
+///
+/// Client errors (4xx) are answered with their message, or with the reason phrase
+/// when they have none. Server errors (5xx) only log their message, the client gets
+/// the default error response. Either way the response goes through the handler
+/// registered with `App::on_error` for the status, which can read the message with
+/// `Response::get_error_detail`, and through problem details, where the message is the `detail`.
+///
+/// Common errors convert into it, so handlers can use `?` on them:
+/// - `std::io::Error`: 404 for missing files, 403 for denied access, 500 otherwise,
+///   without detail, the error itself is only logged
+/// - `serde_json::Error` and number parsing errors: 400
+///
+/// # Example
+///
+///
+/// app.get("/files/:name", handler(|req, _res| -> Result<Vec<u8>, Error> {
+///     let name = req.get_param("name").ok_or(Error::new(HttpStatus::BadRequest))?;
+///     Ok(std::fs::read(format!("uploads/{}", name))?)
+/// }));
+///
+#[derive(Debug)]
+pub struct Error {
+    status: HttpStatus,
+    message: Option<String>,
+}
+
+impl Error {
+    /// An error with the status and no message
+    pub fn new(status: HttpStatus) -> Self {
+        Error { status, message: None }
+    }
+
+    pub fn with_message(status: HttpStatus, message: &str) -> Self {
+        Error { status, message: Some(message.to_string()) }
+    }
+
+    pub fn bad_request(message: &str) -> Self {
+        Error::with_message(HttpStatus::BadRequest, message)
+    }
+
+    pub fn unauthorized(message: &str) -> Self {
+        Error::with_message(HttpStatus::Unauthorized, message)
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        Error::with_message(HttpStatus::Forbidden, message)
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        Error::with_message(HttpStatus::NotFound, message)
+    }
+
+    /// A server error, the message is only logged
+    pub fn internal(message: &str) -> Self {
+        Error::with_message(HttpStatus::InternalServerError, message)
+    }
+
+    pub fn get_status(&self) -> HttpStatus {
+        self.status
+    }
+
+    pub fn get_message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.status, message),
+            None => write!(f, "{}", self.status),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<HttpStatus> for Error {
+    fn from(status: HttpStatus) -> Self {
+        Error::new(status)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        // The text of the error names paths and OS details, so it is only logged
+        let status = match error.kind() {
+            std::io::ErrorKind::NotFound => HttpStatus::NotFound,
+            std::io::ErrorKind::PermissionDenied => HttpStatus::Forbidden,
+            _ => return Error::with_message(HttpStatus::InternalServerError, &error.to_string()),
+        };
+        println!("Error: {}", error);
+        Error::new(status)
+    }
+}
+
+#[cfg(feature = "json")]
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::bad_request(&format!("Invalid JSON: {}", error))
+    }
+}
+
+impl From<std::num::ParseIntError> for Error {
+    fn from(error: std::num::ParseIntError) -> Self {
+        Error::bad_request(&format!("Invalid number: {}", error))
+    }
+}
+
+impl From<std::num::ParseFloatError> for Error {
+    fn from(error: std::num::ParseFloatError) -> Self {
+        Error::bad_request(&format!("Invalid number: {}", error))
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self, res: &mut Response) {
+        match self.message {
+            Some(message) if self.status.code() < 500 => {
+                res.error_with_detail(self.status, &message);
+            },
+            Some(message) => {
+                println!("Error: {}", message);
+                res.error(self.status);
+            },
+            None => {
+                res.error(self.status);
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_error {
+    use super::Error;
+    use crate::http::into_response::IntoResponse;
+    use crate::http::{response::Response, status::HttpStatus};
+
+    fn respond(error: Error) -> Response {
+        let mut res = Response::empty();
+        error.into_response(&mut res);
+        res
+    }
+
+    #[test]
+    fn status_mapping() {
+        let missing = std::fs::read("no/such/file").unwrap_err();
+        assert_eq!(Error::from(missing).get_status(), HttpStatus::NotFound);
+        // io errors are logged, not sent, as they name paths
+        let res = respond(std::fs::read("no/such/file").unwrap_err().into());
+        assert_eq!(res.get_body().unwrap(), b"Not Found");
+        assert_eq!(res.get_error_detail(), None);
+        assert_eq!(Error::from("x".parse::<u32>().unwrap_err()).get_status(), HttpStatus::BadRequest);
+
+        let res = respond(Error::bad_request("Missing name"));
+        assert_eq!(res.get_status(), &HttpStatus::BadRequest);
+        assert_eq!(res.get_body().unwrap(), b"Missing name");
+        assert_eq!(res.get_error_detail(), Some("Missing name"));
+        assert!(res.is_default_error());
+
+        // Server errors don't leak their message
+        let res = respond(Error::internal("database is down"));
+        assert_eq!(res.get_body().unwrap(), b"Internal Server Error");
+        assert_eq!(res.get_error_detail(), None);
+        assert!(res.is_default_error());
+    }
+}
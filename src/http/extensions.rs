@@ -0,0 +1,124 @@
+// Map holding one value of each type
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Values indexed by their type, to pass data along a request that has no field for it.
+///
+/// Layers insert values into the extensions of the request (the authenticated user,
+/// a request id...) and the handlers read them back by type. The response has its own
+/// extensions, to hand data from the handler back to the layers.
+///
+/// Values are shared, so cloning the map is cheap. `get_mut` only works while the
+/// map is not shared, e.g. not in the copy of the request given to `async_handler`.
+///
+/// # Example
+///
+///
+/// struct RequestId(u64);
+///
+/// struct AssignId { next: AtomicU64 }
+///
+/// impl Middleware for AssignId {
+///     fn handle(&self, req: &mut Request, res: &mut Response, next: Next) {
+///         let id = self.next.fetch_add(1, Ordering::Relaxed);
+///         req.extensions_mut().insert(RequestId(id));
+///         next.run(req, res);
+///     }
+/// }
+///
+/// app.get("/", |req, res| {
+///     let id = req.extensions().get::<RequestId>().unwrap();
+///     res.send(&format!("Request {}", id.0))
+/// });
+///
+#[derive(Clone, Default)]
+pub struct Extensions {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Extensions { values: HashMap::new() }
+    }
+
+    /// Insert a value, returning true if it replaced a value of the same type
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> bool {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value)).is_some()
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    /// Mutable access to a value, `None` if it is missing or the map is shared
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        Arc::get_mut(self.values.get_mut(&TypeId::of::<T>())?)?.downcast_mut()
+    }
+
+    /// Remove a value, returning it unless a copy of the map still shares it
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        let value = self.values.remove(&TypeId::of::<T>())?.downcast::<T>().ok()?;
+        Arc::try_unwrap(value).ok()
+    }
+
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    // Shared handle to a value, for the `State` extractor
+    pub(crate) fn get_arc<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        let value = self.values.get(&TypeId::of::<T>())?.clone();
+        value.downcast().ok()
+    }
+
+    // Add the values of another map, replacing the ones of the same type
+    pub(crate) fn extend(&mut self, other: &Extensions) {
+        self.values.extend(other.values.iter().map(|(id, value)| (*id, value.clone())));
+    }
+}
+
+impl std::fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Extensions {{ {} values }}", self.values.len())
+    }
+}
+
+#[cfg(test)]
+mod test_extensions {
+    use super::Extensions;
+
+    #[derive(Debug, PartialEq)]
+    struct UserId(u32);
+
+    #[test]
+    fn typed_values() {
+        let mut extensions = Extensions::new();
+        assert!(!extensions.insert(UserId(1)));
+        assert!(extensions.insert(UserId(7)));
+        extensions.insert("tag");
+        assert_eq!(extensions.len(), 2);
+        assert_eq!(extensions.get::<UserId>(), Some(&UserId(7)));
+        assert_eq!(extensions.get::<&str>(), Some(&"tag"));
+        assert!(!extensions.contains::<String>());
+
+        extensions.get_mut::<UserId>().unwrap().0 += 1;
+        assert_eq!(extensions.get::<UserId>(), Some(&UserId(8)));
+
+        // Shared values can be read but not modified or taken
+        let copy = extensions.clone();
+        assert!(extensions.get_mut::<UserId>().is_none());
+        drop(copy);
+        assert_eq!(extensions.remove::<UserId>(), Some(UserId(8)));
+        assert!(extensions.get::<UserId>().is_none());
+    }
+}
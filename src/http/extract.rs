@@ -0,0 +1,363 @@
+// Typed extraction of handler arguments from the request
+
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use serde::de::DeserializeOwned;
+
+use crate::http::error::Error;
+use crate::http::into_response::IntoResponse;
+use crate::http::{request::Request, response::Response};
+use crate::utils::parsers;
+
+/// Values that can be built from a request, to be used as arguments of the
+/// handlers given to `extract`. A failed extraction answers with its error,
+/// the handler is not called.
+///
+/// # Example
+///
+///
+/// struct ApiKey(String);
+///
+/// impl FromRequest for ApiKey {
+///     fn from_request(req: &Request) -> Result<Self, Error> {
+///         req.get_header_field("X-Api-Key")
+///             .map(|key| ApiKey(key.clone()))
+///             .ok_or(Error::unauthorized("Missing API key"))
+///     }
+/// }
+///
+pub trait FromRequest: Sized {
+    fn from_request(req: &Request) -> Result<Self, Error>;
+}
+
+/// Arguments that are optional, `None` when their extraction fails
+impl<T: FromRequest> FromRequest for Option<T> {
+    fn from_request(req: &Request) -> Result<Self, Error> {
+        Ok(T::from_request(req).ok())
+    }
+}
+
+/// Parameters of the route (e.g. `:id` in `/users/:id`).
+/// A single parameter deserializes into a plain value, several ones into a struct
+/// with a field per parameter.
+///
+/// # Example
+///
+///
+/// app.get("/users/:id", extract(|Path(id): Path<u32>| format!("User {}", id)));
+///
+/// #[derive(Deserialize)]
+/// struct PostPath { user: u32, post: String }
+///
+/// app.get("/users/:user/posts/:post", extract(|Path(path): Path<PostPath>| path.post));
+///
+#[derive(Debug, Clone)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(req: &Request) -> Result<Self, Error> {
+        // The params are raw segments of the uri, where `+` is not a space
+        let params: HashMap<&String, String> = req.get_params().iter()
+            .map(|(name, value)| (name, parsers::percent_decode(&value.replace('+', "%2B"))))
+            .collect();
+        let encoded = serde_urlencoded::to_string(&params).unwrap_or_default();
+        let as_struct = serde_urlencoded::from_str::<T>(&encoded);
+        if params.len() != 1 || as_struct.is_ok() {
+            return as_struct.map(Path)
+                .map_err(|e| Error::bad_request(&format!("Invalid path parameters: {}", e)));
+        }
+        // A single parameter, deserialized on its own
+        let (name, _) = params.iter().next().unwrap();
+        serde_urlencoded::from_str::<HashMap<String, T>>(&encoded)
+            .map_err(|e| Error::bad_request(&format!("Invalid path parameter `{}`: {}", name, e)))
+            .map(|mut value| Path(value.remove(*name).unwrap()))
+    }
+}
+
+/// Query string of the uri, deserialized into a type.
+/// Use `Option` fields for the parameters that may be missing.
+///
+/// # Example
+///
+///
+/// #[derive(Deserialize)]
+/// struct Search { q: String, page: Option<u32> }
+///
+/// app.get("/search", extract(|Query(search): Query<Search>| {
+///     format!("{} (page {})", search.q, search.page.unwrap_or(1))
+/// }));
+///
+#[derive(Debug, Clone)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(req: &Request) -> Result<Self, Error> {
+        let query = req.get_uri().split_once('?').map(|(_, query)| query).unwrap_or_default();
+        serde_urlencoded::from_str(query)
+            .map(Query)
+            .map_err(|e| Error::bad_request(&format!("Invalid query string: {}", e)))
+    }
+}
+
+/// JSON body, deserialized like `Request::json`
+#[cfg(feature = "json")]
+#[derive(Debug, Clone)]
+pub struct Json<T>(pub T);
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    fn from_request(req: &Request) -> Result<Self, Error> {
+        req.json().map(Json)
+    }
+}
+
+/// Form body, deserialized like `Request::form_as`
+#[derive(Debug, Clone)]
+pub struct Form<T>(pub T);
+
+impl<T: DeserializeOwned> FromRequest for Form<T> {
+    fn from_request(req: &Request) -> Result<Self, Error> {
+        req.form_as().map(Form)
+    }
+}
+
+/// Headers that can be read with the `Header` extractor
+pub trait TypedHeader: Sized {
+    /// Name of the header, e.g. `User-Agent`
+    const NAME: &'static str;
+
+    fn decode(value: &str) -> Result<Self, String>;
+}
+
+/// A header of the request, decoded into its `TypedHeader`.
+/// Fails with `400 Bad Request` when the header is missing or invalid,
+/// use `Option<Header<T>>` for optional headers.
+///
+/// # Example
+///
+///
+/// app.get("/agent", extract(|Header(agent): Header<UserAgent>| agent.0));
+///
+#[derive(Debug, Clone)]
+pub struct Header<T>(pub T);
+
+impl<T: TypedHeader> FromRequest for Header<T> {
+    fn from_request(req: &Request) -> Result<Self, Error> {
+        let value = req.get_header_field(T::NAME)
+            .ok_or(Error::bad_request(&format!("Missing header {}", T::NAME)))?;
+        T::decode(value)
+            .map(Header)
+            .map_err(|e| Error::bad_request(&format!("Invalid header {}: {}", T::NAME, e)))
+    }
+}
+
+/// The `User-Agent` header
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserAgent(pub String);
+
+impl TypedHeader for UserAgent {
+    const NAME: &'static str = "User-Agent";
+
+    fn decode(value: &str) -> Result<Self, String> {
+        Ok(UserAgent(value.to_string()))
+    }
+}
+
+/// The `Content-Type` header
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentType(pub String);
+
+impl TypedHeader for ContentType {
+    const NAME: &'static str = "Content-Type";
+
+    fn decode(value: &str) -> Result<Self, String> {
+        Ok(ContentType(value.to_string()))
+    }
+}
+
+/// The `Content-Length` header
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentLength(pub u64);
+
+impl TypedHeader for ContentLength {
+    const NAME: &'static str = "Content-Length";
+
+    fn decode(value: &str) -> Result<Self, String> {
+        value.trim().parse().map(ContentLength).map_err(|e| format!("{}", e))
+    }
+}
+
+/// The `Authorization` header
+#[derive(Debug, Clone, PartialEq)]
+pub struct Authorization(pub String);
+
+impl TypedHeader for Authorization {
+    const NAME: &'static str = "Authorization";
+
+    fn decode(value: &str) -> Result<Self, String> {
+        Ok(Authorization(value.to_string()))
+    }
+}
+
+/// Shared state of the application, see `App::with_state`.
+/// Its absence is a server error, as the application was not set up for the handler.
+#[derive(Debug)]
+pub struct State<T>(pub Arc<T>);
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> Self {
+        State(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Send + Sync + 'static> FromRequest for State<T> {
+    fn from_request(req: &Request) -> Result<Self, Error> {
+        req.state::<T>()
+            .map(State)
+            .ok_or(Error::internal(&format!("Missing state of type {}", std::any::type_name::<T>())))
+    }
+}
+
+/// Functions whose arguments are all extractors, see `extract`
+pub trait ExtractHandler<Args> {
+    fn call(&self, req: &Request, res: &mut Response);
+}
+
+macro_rules! extract_handler {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg),*> ExtractHandler<($($arg,)*)> for F
+            where F: Fn($($arg),*) -> R,
+                  R: IntoResponse,
+                  $($arg: FromRequest),*
+        {
+            #[allow(non_snake_case)]
+            fn call(&self, _req: &Request, res: &mut Response) {
+                $(
+                    let $arg = match $arg::from_request(_req) {
+                        Ok(value) => value,
+                        Err(error) => return error.into_response(res),
+                    };
+                )*
+                self($($arg),*).into_response(res);
+            }
+        }
+    };
+}
+
+extract_handler!();
+extract_handler!(A1);
+extract_handler!(A1, A2);
+extract_handler!(A1, A2, A3);
+extract_handler!(A1, A2, A3, A4);
+extract_handler!(A1, A2, A3, A4, A5);
+extract_handler!(A1, A2, A3, A4, A5, A6);
+
+/// Turn a function taking extractors into a route action. The arguments are
+/// extracted in order, the first one that fails answers with its error
+/// (`400 Bad Request` with a message for invalid input) and the function is not called.
+/// Its output is written into the response with `IntoResponse`.
+///
+/// # Example
+///
+///
+/// #[derive(Deserialize)]
+/// struct Paging { page: Option<u32> }
+///
+/// fn list_posts(Path(user): Path<u32>, Query(paging): Query<Paging>, State(db): State<Db>) -> Result<String, Error> {
+///     let posts = db.posts(user, paging.page.unwrap_or(1))?;
+///     Ok(posts.join("\n"))
+/// }
+///
+/// app.get("/users/:user/posts", extract(list_posts));
+///
+pub fn extract<F, Args>(action: F) -> impl for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync
+    where F: ExtractHandler<Args> + Send + Sync
+{
+    move |req, res| {
+        action.call(req, res);
+        res
+    }
+}
+
+#[cfg(test)]
+mod test_extract {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::http::methods::HttpMethod;
+    use crate::http::status::HttpStatus;
+
+    #[derive(Deserialize)]
+    struct Search {
+        q: String,
+        page: Option<u32>,
+    }
+
+    fn request(uri: &str, params: &[(&str, &str)]) -> Request {
+        let params: HashMap<String, String> = params.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let headers = HashMap::from([("User-Agent".to_string(), "curl/8.0".to_string())]);
+        Request::create(uri, HttpMethod::GET, params, Vec::new(), headers)
+    }
+
+    fn run<Args>(action: impl ExtractHandler<Args> + Send + Sync, req: &Request) -> (HttpStatus, String) {
+        let mut res = Response::empty();
+        res.set_status(HttpStatus::Ok);
+        extract(action)(req, &mut res);
+        let body = String::from_utf8_lossy(res.get_body().unwrap()).to_string();
+        (*res.get_status(), body)
+    }
+
+    #[test]
+    fn extractors() {
+        let req = request("/users/7?q=rust+web&page=2", &[("id", "7")]);
+        let (status, body) = run(|Path(id): Path<u32>, Query(search): Query<Search>, Header(agent): Header<UserAgent>| {
+            format!("{} {} {} {}", id, search.q, search.page.unwrap(), agent.0)
+        }, &req);
+        assert_eq!(status, HttpStatus::Ok);
+        assert_eq!(body, "7 rust web 2 curl/8.0");
+
+        #[derive(Deserialize)]
+        struct PostPath { user: u32, post: String }
+        let req = request("/users/3/posts/hello", &[("user", "3"), ("post", "hello")]);
+        let (_, body) = run(|Path(path): Path<PostPath>, length: Option<Header<ContentLength>>| {
+            format!("{} {} {}", path.user, path.post, length.is_none())
+        }, &req);
+        assert_eq!(body, "3 hello true");
+
+        // Segments are percent-decoded
+        let req = request("/users/a%20b+c", &[("name", "a%20b+c")]);
+        let (_, body) = run(|Path(name): Path<String>| name, &req);
+        assert_eq!(body, "a b+c");
+    }
+
+    #[test]
+    fn failed_extraction() {
+        let req = request("/users/abc", &[("id", "abc")]);
+        let (status, body) = run(|Path(id): Path<u32>| format!("{}", id), &req);
+        assert_eq!(status, HttpStatus::BadRequest);
+        assert!(body.starts_with("Invalid path parameter `id`"));
+
+        let req = request("/search?page=2", &[]);
+        let (status, body) = run(|Query(search): Query<Search>| search.q, &req);
+        assert_eq!(status, HttpStatus::BadRequest);
+        assert!(body.contains("missing field `q`"));
+
+        let (_, body) = run(|Header(length): Header<ContentLength>| format!("{}", length.0), &req);
+        assert_eq!(body, "Missing header Content-Length");
+
+        let (status, _) = run(|State(count): State<u32>| format!("{}", count), &req);
+        assert_eq!(status, HttpStatus::InternalServerError);
+    }
+}
@@ -0,0 +1,45 @@
+// Decoded application/x-www-form-urlencoded data
+
+/// Ordered form fields where a name may appear several times
+/// (e.g. checkboxes or `<select multiple>`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FormData {
+    fields: Vec<(String, String)>,
+}
+
+impl FormData {
+    pub fn new(fields: Vec<(String, String)>) -> Self {
+        FormData { fields }
+    }
+
+    /// First value of the field
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the field, in the order they were sent
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.fields.iter()
+            .filter(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.fields.iter().any(|(key, _)| key == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
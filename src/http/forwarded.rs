@@ -0,0 +1,224 @@
+// Client address, scheme and host forwarded by trusted proxies
+
+use std::net::{IpAddr, SocketAddr};
+
+use crate::http::request::Request;
+use crate::utils::cidr::Cidr;
+
+/// The header in which the trusted proxies forward the client, see `App::trusted_proxies`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyHeader {
+    /// `Forwarded` (RFC 7239)
+    Forwarded,
+    /// `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host`
+    XForwarded,
+}
+
+// What a proxy knew about the connection it received
+#[derive(Debug, Default, Clone)]
+struct Hop {
+    addr: Option<String>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value)
+}
+
+// Elements of the Forwarded header (RFC 7239), e.g. `for=192.0.2.60;proto=https;host=example.com`
+fn parse_forwarded(header: &str) -> Vec<Hop> {
+    header.split(',').map(|element| {
+        let mut hop = Hop::default();
+        for pair in element.split(';') {
+            let Some((name, value)) = pair.split_once('=') else { continue };
+            let value = unquote(value).to_string();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "for" => hop.addr = Some(value),
+                "proto" => hop.proto = Some(value),
+                "host" => hop.host = Some(value),
+                _ => {},
+            }
+        }
+        hop
+    }).collect()
+}
+
+fn split_list(header: Option<&String>) -> Vec<String> {
+    header.map(|value| value.split(',').map(|item| item.trim().to_string()).collect())
+        .unwrap_or_default()
+}
+
+// X-Forwarded-For, with the proto and host lists matched by position when they have
+// an entry for every hop, otherwise their last value applies to every hop
+fn parse_x_forwarded(req: &Request) -> Vec<Hop> {
+    let addrs = split_list(req.get_header_field("X-Forwarded-For"));
+    let protos = split_list(req.get_header_field("X-Forwarded-Proto"));
+    let hosts = split_list(req.get_header_field("X-Forwarded-Host"));
+    let pick = |values: &Vec<String>, i: usize| match values.len() == addrs.len() {
+        true => values.get(i).cloned(),
+        false => values.last().cloned(),
+    };
+    let mut hops: Vec<Hop> = addrs.iter().enumerate().map(|(i, addr)| Hop {
+        addr: Some(addr.clone()),
+        proto: pick(&protos, i),
+        host: pick(&hosts, i),
+    }).collect();
+    if hops.is_empty() && (!protos.is_empty() || !hosts.is_empty()) {
+        hops.push(Hop { addr: None, proto: protos.last().cloned(), host: hosts.last().cloned() });
+    }
+    hops
+}
+
+// `192.0.2.43`, `192.0.2.43:47011`, `[2001:db8::17]:4711` or `2001:db8::17`
+fn parse_addr(value: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = value.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    let ip = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')).unwrap_or(value);
+    ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 0))
+}
+
+fn valid_proto(proto: &str) -> bool {
+    proto.eq_ignore_ascii_case("http") || proto.eq_ignore_ascii_case("https")
+}
+
+fn valid_host(host: &str) -> bool {
+    !host.is_empty() && host.chars().all(|c| c.is_ascii_alphanumeric() || "-.:[]_".contains(c))
+}
+
+/// Replace the client address, scheme and host of a request coming from a trusted
+/// proxy with the ones it forwarded. Only the header written by the proxies is read,
+/// as they pass the other one along untouched from the client. The hops are walked
+/// from the nearest one, skipping the trusted proxies, so a client can't spoof its
+/// address by sending the headers itself.
+pub(crate) fn resolve(req: &mut Request, proxies: &[Cidr], header: ProxyHeader) {
+    let trusted = |addr: &SocketAddr| proxies.iter().any(|range| range.contains(addr.ip()));
+    match req.peer_addr() {
+        Some(peer) if trusted(&peer) => {},
+        _ => return,
+    }
+
+    let hops = match header {
+        ProxyHeader::Forwarded => req.get_header_field("Forwarded").map(|value| parse_forwarded(value)).unwrap_or_default(),
+        ProxyHeader::XForwarded => parse_x_forwarded(req),
+    };
+
+    // The hop describing the request sent by the client
+    let mut client = None;
+    for hop in hops.iter().rev() {
+        let addr = match hop.addr.as_deref().map(parse_addr) {
+            Some(Some(addr)) => addr,
+            // Obfuscated or malformed address, nothing before it can be trusted
+            Some(None) => break,
+            None => {
+                client = Some((None, hop));
+                break;
+            },
+        };
+        client = Some((Some(addr), hop));
+        if !trusted(&addr) {
+            break;
+        }
+    }
+
+    if let Some((addr, hop)) = client {
+        if let Some(addr) = addr {
+            req.set_remote_addr(addr);
+        }
+        if let Some(proto) = hop.proto.as_deref().filter(|proto| valid_proto(proto)) {
+            req.set_scheme(&proto.to_ascii_lowercase());
+        }
+        if let Some(host) = hop.host.as_deref().filter(|host| valid_host(host)) {
+            req.set_host(host);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_forwarded {
+    use super::{resolve, ProxyHeader};
+    use crate::http::request::Request;
+    use crate::utils::cidr::{parse_list, Cidr};
+
+    fn request(peer: &str, header: ProxyHeader, headers: &[(&str, &str)]) -> Request {
+        let mut req = Request::new("GET / HTTP/1.1\r\nHost: internal:8080\r\n\r\n").unwrap();
+        req.set_peer_addr(peer.parse().unwrap());
+        for (name, value) in headers {
+            req.set_header_field(name, value);
+        }
+        let proxies: Vec<Cidr> = parse_list(&["10.0.0.0/8"]).unwrap();
+        resolve(&mut req, &proxies, header);
+        req
+    }
+
+    #[test]
+    fn forwarded_header() {
+        let req = request("10.0.0.2:5000", ProxyHeader::Forwarded, &[
+            ("Forwarded", r#"for="[2001:db8:cafe::17]:4711";proto=https;host=example.com, for=10.0.0.9"#),
+        ]);
+        assert_eq!(req.remote_addr().unwrap().to_string(), "[2001:db8:cafe::17]:4711");
+        assert_eq!(req.peer_addr().unwrap().to_string(), "10.0.0.2:5000");
+        assert_eq!(req.scheme(), "https");
+        assert_eq!(req.host(), Some("example.com"));
+    }
+
+    #[test]
+    fn x_forwarded_headers() {
+        let req = request("10.0.0.2:5000", ProxyHeader::XForwarded, &[
+            ("X-Forwarded-For", "6.6.6.6, 203.0.113.7, 10.0.0.5"),
+            ("X-Forwarded-Proto", "https"),
+            ("X-Forwarded-Host", "shop.example.com"),
+        ]);
+        // The spoofed leftmost address is ignored
+        assert_eq!(req.remote_addr().unwrap().ip().to_string(), "203.0.113.7");
+        assert_eq!(req.scheme(), "https");
+        assert_eq!(req.host(), Some("shop.example.com"));
+    }
+
+    #[test]
+    fn repeated_headers() {
+        let proxies: Vec<Cidr> = parse_list(&["10.0.0.0/8"]).unwrap();
+        let resolved = |head: &str| {
+            let mut req = Request::new(&format!("GET / HTTP/1.1\r\nHost: internal:8080\r\n{}\r\n", head)).unwrap();
+            req.set_peer_addr("10.0.0.2:5000".parse().unwrap());
+            resolve(&mut req, &proxies, ProxyHeader::XForwarded);
+            req.remote_addr().unwrap().ip().to_string()
+        };
+        // The lines are joined in order, the one added by the proxy comes last
+        assert_eq!(resolved("X-Forwarded-For: 6.6.6.6\r\nX-Forwarded-For: 203.0.113.7\r\n"), "203.0.113.7");
+        assert_eq!(resolved("X-Forwarded-For: 6.6.6.6\r\nx-forwarded-for: 203.0.113.7\r\n"), "203.0.113.7");
+        assert_eq!(resolved("x-forwarded-for: 203.0.113.7, 10.0.0.5\r\nX-FORWARDED-FOR: 10.0.0.6\r\n"), "203.0.113.7");
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let req = request("198.51.100.1:5000", ProxyHeader::XForwarded, &[
+            ("X-Forwarded-For", "203.0.113.7"),
+            ("X-Forwarded-Proto", "https"),
+        ]);
+        assert_eq!(req.remote_addr().unwrap().to_string(), "198.51.100.1:5000");
+        assert_eq!(req.scheme(), "http");
+        assert_eq!(req.host(), Some("internal:8080"));
+    }
+
+    #[test]
+    fn only_the_header_of_the_proxies() {
+        // The proxy only appends to X-Forwarded-For, the Forwarded header comes from the client
+        let req = request("10.0.0.2:5000", ProxyHeader::XForwarded, &[
+            ("Forwarded", "for=192.168.1.1;proto=https;host=admin.internal"),
+            ("X-Forwarded-For", "203.0.113.7"),
+        ]);
+        assert_eq!(req.remote_addr().unwrap().ip().to_string(), "203.0.113.7");
+        assert_eq!(req.scheme(), "http");
+        assert_eq!(req.host(), Some("internal:8080"));
+
+        // And the other way around
+        let req = request("10.0.0.2:5000", ProxyHeader::Forwarded, &[
+            ("X-Forwarded-For", "192.168.1.1"),
+        ]);
+        assert_eq!(req.remote_addr().unwrap().to_string(), "10.0.0.2:5000");
+    }
+}
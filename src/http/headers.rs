@@ -0,0 +1,102 @@
+// Multi-valued header map used by the response
+
+use std::io::{Error, ErrorKind};
+
+/// Ordered list of headers where a name may appear several times
+/// (e.g. `Set-Cookie`). Names are compared ignoring ASCII case.
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Headers { entries: Vec::new() }
+    }
+
+    /// Replace every value of the header with a single one.
+    /// Returns the first previous value, like `HashMap::insert`.
+    ///
+    /// Fails if the name is not a valid header name or the value holds a line break,
+    /// which would let it inject headers into the response.
+    pub fn try_insert(&mut self, name: String, value: String) -> Result<Option<String>, Error> {
+        check(&name, &value)?;
+        let old = self.remove(&name);
+        self.entries.push((name, value));
+        Ok(old)
+    }
+
+    /// Add a value without touching the existing ones, failing like `try_insert`
+    pub fn try_append(&mut self, name: String, value: String) -> Result<(), Error> {
+        check(&name, &value)?;
+        self.entries.push((name, value));
+        Ok(())
+    }
+
+    /// Like `try_insert`, for names and values known to be valid
+    ///
+    /// # Panics
+    ///
+    /// If the header is invalid
+    pub fn insert(&mut self, name: String, value: String) -> Option<String> {
+        self.try_insert(name, value).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `try_append`, panicking if the header is invalid
+    pub fn append(&mut self, name: String, value: String) {
+        self.try_append(name, value).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Remove every value of the header and return the first one
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+        self.entries.retain(|(key, value)| {
+            if !key.eq_ignore_ascii_case(name) {
+                return true;
+            }
+            if removed.is_none() {
+                removed = Some(value.clone());
+            }
+            false
+        });
+        removed
+    }
+
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&String> {
+        self.entries.iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+impl From<std::collections::HashMap<String, String>> for Headers {
+    fn from(map: std::collections::HashMap<String, String>) -> Self {
+        for (name, value) in &map {
+            check(name, value).unwrap_or_else(|e| panic!("{}", e));
+        }
+        Headers { entries: map.into_iter().collect() }
+    }
+}
+
+// Names are tokens and values may not break the line (CR, LF or NUL)
+fn check(name: &str, value: &str) -> Result<(), Error> {
+    let token = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(token) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid header name {:?}", name)));
+    }
+    if value.contains(['\r', '\n', '\0']) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Invalid value for header {}: {:?}", name, value)));
+    }
+    Ok(())
+}
@@ -0,0 +1,223 @@
+// Conversion of handler return values into responses
+
+use std::future::Future;
+use std::panic;
+use std::thread;
+
+use tokio::runtime::{Builder, Handle, RuntimeFlavor};
+
+use crate::http::{request::Request, response::Response, status::HttpStatus};
+
+/// Values that a handler can return to build its response.
+///
+/// The value is written into the response of the request, so the headers
+/// already set by the layers (cookies, security headers...) are kept.
+///
+/// # Example
+///
+///
+/// struct Greeting(String);
+///
+/// impl IntoResponse for Greeting {
+///     fn into_response(self, res: &mut Response) {
+///         res.send(&format!("Hello, {}!", self.0));
+///     }
+/// }
+///
+pub trait IntoResponse {
+    fn into_response(self, res: &mut Response);
+}
+
+/// Keep the response as the handler left it
+impl IntoResponse for () {
+    fn into_response(self, _res: &mut Response) {}
+}
+
+impl IntoResponse for &str {
+    fn into_response(self, res: &mut Response) {
+        res.send(self);
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self, res: &mut Response) {
+        res.send(&self);
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self, res: &mut Response) {
+        res.set_header("Content-Type", "application/octet-stream");
+        res.set_body(self);
+    }
+}
+
+#[cfg(feature = "json")]
+impl IntoResponse for serde_json::Value {
+    fn into_response(self, res: &mut Response) {
+        res.json(&self.to_string());
+    }
+}
+
+impl IntoResponse for HttpStatus {
+    fn into_response(self, res: &mut Response) {
+        match self.is_error() {
+            true => res.error(self),
+            false => res.send(self.reason()),
+        };
+        res.set_status(self);
+    }
+}
+
+/// A body with another status than `200 OK`
+impl<T: IntoResponse> IntoResponse for (HttpStatus, T) {
+    fn into_response(self, res: &mut Response) {
+        self.1.into_response(res);
+        res.set_status(self.0);
+    }
+}
+
+impl IntoResponse for Response {
+    fn into_response(self, res: &mut Response) {
+        res.merge(self);
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self, res: &mut Response) {
+        match self {
+            Ok(value) => value.into_response(res),
+            Err(error) => error.into_response(res),
+        }
+    }
+}
+
+/// Turn a handler returning a `Result` into a route action, so it can use `?`.
+/// Both the value and the error are written into the response with `IntoResponse`.
+///
+/// # Example
+///
+///
+/// app.get("/users/:id", handler(|req, _res| -> Result<String, Error> {
+///     let id: u32 = req.get_param("id").ok_or(Error::bad_request("Missing id"))?.parse()?;
+///     let user = find_user(id).ok_or(Error::not_found("No such user"))?;
+///     Ok(user.name)
+/// }));
+///
+pub fn handler<F, T, E>(action: F) -> impl for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync
+    where F: Fn(&Request, &mut Response) -> Result<T, E> + Send + Sync,
+          T: IntoResponse,
+          E: IntoResponse
+{
+    move |req, res| {
+        action(req, res).into_response(res);
+        res
+    }
+}
+
+/// Turn an async function into a route action. It gets a copy of the request
+/// and its output is written into the response with `IntoResponse`.
+///
+/// When the request has a deadline, set by the `Timeout` layer, the future is
+/// dropped once it passes and the timeout response is sent instead.
+///
+/// # Example
+///
+///
+/// app.get("/report", async_handler(|req| async move {
+///     let report = build_report(req.get_param("id").cloned()).await?;
+///     Ok::<_, Error>(report)
+/// }));
+///
+pub fn async_handler<F, Fut, T>(action: F) -> impl for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync
+    where F: Fn(Request) -> Fut + Send + Sync,
+          Fut: Future<Output = T> + Send,
+          T: IntoResponse + Send
+{
+    move |req, res| {
+        let future = action(req.clone());
+        let deadline = req.deadline();
+        let output = block_on(async move {
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline.into(), future).await.ok(),
+                None => Some(future.await),
+            }
+        });
+        match (output, req.timeout_status()) {
+            (Some(output), _) => output.into_response(res),
+            (None, status) => {
+                println!("Timeout on {} {} (route {})", req.get_method(), req.get_uri(), req.get_route().unwrap_or("none"));
+                res.error(status.unwrap_or(HttpStatus::ServiceUnavailable));
+            },
+        }
+        res
+    }
+}
+
+// Handlers run on the blocking threads of the server runtime. On a worker of a
+// multi-thread runtime, the worker hands its other tasks over before blocking.
+// A current thread runtime can't be blocked, the future gets a thread and a
+// throwaway runtime of its own, like outside of a runtime (e.g. in tests).
+fn block_on<F>(future: F) -> F::Output
+    where F: Future + Send,
+          F::Output: Send
+{
+    let runtime = || Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to start a runtime for the handler");
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        },
+        Ok(_) => thread::scope(|scope| {
+            scope.spawn(|| runtime().block_on(future))
+                .join()
+                .unwrap_or_else(|payload| panic::resume_unwind(payload))
+        }),
+        Err(_) => runtime().block_on(future),
+    }
+}
+
+
+#[cfg(test)]
+mod test_into_response {
+    use super::IntoResponse;
+    use crate::http::{response::Response, status::HttpStatus};
+
+    fn build<T: IntoResponse>(value: T) -> Response {
+        let mut res = Response::empty();
+        res.set_status(HttpStatus::Ok);
+        res.set_header("X-Frame-Options", "DENY");
+        value.into_response(&mut res);
+        res
+    }
+
+    #[test]
+    fn conversions() {
+        let res = build("hello");
+        assert_eq!(res.get_status(), &HttpStatus::Ok);
+        assert_eq!(res.get_header("Content-Type").unwrap(), "text/plain");
+        assert_eq!(res.get_body().unwrap(), b"hello");
+
+        #[cfg(feature = "json")]
+        {
+            let res = build((HttpStatus::BadRequest, serde_json::json!({"field": "name"})));
+            assert_eq!(res.get_status(), &HttpStatus::BadRequest);
+            assert_eq!(res.get_header("Content-Type").unwrap(), "application/json");
+            assert_eq!(res.get_body().unwrap(), br#"{"field":"name"}"#);
+        }
+
+        let res = build(HttpStatus::NotFound);
+        assert!(res.is_default_error());
+
+        let mut other = Response::empty();
+        other.set_status(HttpStatus::Forbidden);
+        other.set_header("Content-Type", "text/html");
+        let res = build(other);
+        assert_eq!(res.get_status(), &HttpStatus::Forbidden);
+        assert_eq!(res.get_header("Content-Type").unwrap(), "text/html");
+        // Headers of the layers are kept
+        assert_eq!(res.get_header("X-Frame-Options").unwrap(), "DENY");
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt::{self, Debug};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone)]
 pub enum HttpMethod {
     GET,
@@ -2,3 +2,15 @@ pub(crate) mod methods;
 pub(crate) mod request;
 pub(crate) mod response;
 pub(crate) mod status;
+pub(crate) mod headers;
+pub(crate) mod cookie;
+pub(crate) mod cookie_jar;
+pub(crate) mod into_response;
+pub(crate) mod error;
+#[cfg(feature = "json")]
+pub(crate) mod problem;
+pub(crate) mod forwarded;
+pub(crate) mod form;
+pub(crate) mod multipart;
+pub(crate) mod extract;
+pub(crate) mod extensions;
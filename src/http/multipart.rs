@@ -0,0 +1,301 @@
+// multipart/form-data bodies (RFC 7578), for file uploads
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::http::error::Error;
+use crate::http::request::Request;
+use crate::http::status::HttpStatus;
+
+const MAX_FILE_SIZE: usize = 10 * 1024 * 1024;
+const MAX_TOTAL_SIZE: usize = 50 * 1024 * 1024;
+
+fn malformed(reason: &str) -> Error {
+    Error::bad_request(&format!("Malformed multipart body: {}", reason))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+// Split a header value like `form-data; name="file"; filename="a.txt"` into its parameters
+fn header_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = match value.split_once(';') {
+        Some((_, rest)) => rest,
+        None => return params,
+    };
+    loop {
+        rest = rest.trim_start_matches([' ', ';']);
+        let Some((name, after)) = rest.split_once('=') else { break };
+        let name = name.trim().to_ascii_lowercase();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => if let Some((_, escaped)) = chars.next() { value.push(escaped) },
+                        '"' => { end = i + 1; break },
+                        c => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            },
+            None => match after.split_once(';') {
+                Some((value, after)) => (value.trim().to_string(), after),
+                None => (after.trim().to_string(), ""),
+            },
+        };
+        params.push((name, value));
+        rest = after;
+    }
+    params
+}
+
+/// A field or file of a multipart body, whose content is borrowed from the body
+#[derive(Debug)]
+pub struct Part<'a> {
+    name: String,
+    filename: Option<String>,
+    headers: Vec<(String, String)>,
+    content: &'a [u8],
+}
+
+impl Part<'_> {
+    /// Name of the form field
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// File name sent by the client. Never use it as a path without sanitizing it.
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// Content type of the part, `text/plain` when not sent
+    pub fn content_type(&self) -> &str {
+        self.header("Content-Type").unwrap_or("text/plain")
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// Size of the content in bytes
+    pub fn size(&self) -> usize {
+        self.content.len()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        self.content
+    }
+
+    /// Content of the part as text
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(self.content).to_string()
+    }
+
+    /// Save the content to a file
+    pub fn persist<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.content)
+    }
+}
+
+/// Parser of a `multipart/form-data` body, yielding its parts one at a time.
+///
+/// A file over `max_file_size` (10 MiB) or a body whose parts add up to more than
+/// `max_total_size` (50 MiB) fails with `413 Payload Too Large`; a malformed body fails
+/// with `400 Bad Request`. The parser stops after the first error. The body itself is
+/// bounded by `App::body_limit`, which must be raised to accept big uploads.
+///
+/// The body is read into memory before the handler runs and the parts borrow their
+/// content from it, nothing is streamed from the connection or spooled to disk.
+///
+/// # Example
+///
+///
+/// app.post("/upload", handler(|req, _res| -> Result<String, Error> {
+///     let mut saved = Vec::new();
+///     for part in req.multipart()?.max_file_size(2 * 1024 * 1024) {
+///         let part = part?;
+///         if part.is_file() && part.content_type() == "image/png" {
+///             let name = format!("uploads/{}.png", random_id());
+///             part.persist(&name)?;
+///             saved.push(name);
+///         }
+///     }
+///     Ok(saved.join("\n"))
+/// }));
+///
+pub struct Multipart<'a> {
+    body: &'a [u8],
+    delimiter: Vec<u8>,
+    position: usize,
+    done: bool,
+    total: usize,
+    max_file_size: usize,
+    max_total_size: usize,
+}
+
+impl<'a> Multipart<'a> {
+    /// Parser for the body of a request.
+    /// Fails with `415 Unsupported Media Type` if it is not `multipart/form-data`.
+    pub fn new(req: &'a Request) -> Result<Self, Error> {
+        if req.media_type().as_deref() != Some("multipart/form-data") {
+            return Err(Error::with_message(HttpStatus::UnsupportedMediaType, "Expected a multipart/form-data body"));
+        }
+        let content_type = req.get_header_field("Content-Type").map(String::as_str).unwrap_or_default();
+        let boundary = header_params(content_type).into_iter()
+            .find(|(name, _)| name == "boundary")
+            .map(|(_, value)| value)
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+            .ok_or_else(|| malformed("missing boundary"))?;
+        Ok(Multipart {
+            body: req.get_body().map(Vec::as_slice).unwrap_or_default(),
+            delimiter: format!("--{}", boundary).into_bytes(),
+            position: 0,
+            done: false,
+            total: 0,
+            max_file_size: MAX_FILE_SIZE,
+            max_total_size: MAX_TOTAL_SIZE,
+        })
+    }
+
+    /// Largest file accepted, in bytes
+    pub fn max_file_size(mut self, bytes: usize) -> Self {
+        self.max_file_size = bytes;
+        self
+    }
+
+    /// Largest size of all the parts together, in bytes
+    pub fn max_total_size(mut self, bytes: usize) -> Self {
+        self.max_total_size = bytes;
+        self
+    }
+
+    fn next_part(&mut self) -> Result<Option<Part<'a>>, Error> {
+        let body = self.body;
+        let rest = &body[self.position..];
+
+        // The first delimiter may follow a preamble, the next ones follow a part
+        let start = match self.position {
+            0 => find(rest, &self.delimiter).ok_or_else(|| malformed("missing boundary"))?,
+            _ => 0,
+        };
+        let after = &rest[start + self.delimiter.len()..];
+        if after.starts_with(b"--") {
+            return Ok(None);
+        }
+        let after = after.strip_prefix(b"\r\n").ok_or_else(|| malformed("invalid boundary line"))?;
+        let offset = self.body.len() - after.len();
+
+        let head_end = find(after, b"\r\n\r\n").ok_or_else(|| malformed("unterminated part headers"))?;
+        let head = String::from_utf8_lossy(&after[..head_end]).to_string();
+        let mut headers = Vec::new();
+        for line in head.split("\r\n").filter(|line| !line.is_empty()) {
+            let (name, value) = line.split_once(':').ok_or_else(|| malformed("invalid part header"))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let content_start = offset + head_end + 4;
+        let mut closing = b"\r\n".to_vec();
+        closing.extend(&self.delimiter);
+        let content_length = find(&self.body[content_start..], &closing).ok_or_else(|| malformed("missing closing boundary"))?;
+        let content = &body[content_start..content_start + content_length];
+        self.position = content_start + content_length + 2;
+
+        let disposition = headers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("Content-Disposition"))
+            .map(|(_, value)| header_params(value))
+            .ok_or_else(|| malformed("part without Content-Disposition"))?;
+        let param = |name: &str| disposition.iter().find(|(key, _)| key == name).map(|(_, value)| value.clone());
+        let name = param("name").ok_or_else(|| malformed("part without name"))?;
+        let filename = param("filename");
+
+        if filename.is_some() && content.len() > self.max_file_size {
+            return Err(Error::with_message(HttpStatus::PayloadTooLarge, "File too large"));
+        }
+        self.total += content.len();
+        if self.total > self.max_total_size {
+            return Err(Error::with_message(HttpStatus::PayloadTooLarge, "Upload too large"));
+        }
+        Ok(Some(Part { name, filename, headers, content }))
+    }
+}
+
+impl<'a> Iterator for Multipart<'a> {
+    type Item = Result<Part<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let part = self.next_part();
+        if !matches!(part, Ok(Some(_))) {
+            self.done = true;
+        }
+        part.transpose()
+    }
+}
+
+
+#[cfg(test)]
+mod test_multipart {
+    use super::Multipart;
+    use crate::http::{request::Request, status::HttpStatus};
+
+    fn request(body: &str) -> Request {
+        let head = "POST /upload HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=\"XyZ\"\r\n\r\n";
+        Request::new(&format!("{}{}", head, body.replace('\n', "\r\n"))).unwrap()
+    }
+
+    const BODY: &str = "preamble\n--XyZ\nContent-Disposition: form-data; name=\"title\"\n\nHoliday\n--XyZ\nContent-Disposition: form-data; name=\"photo\"; filename=\"beach \\\"1\\\".png\"\nContent-Type: image/png\nX-Checksum: abc\n\nPNGDATA-PNGDATA\n--XyZ--\n";
+
+    #[test]
+    fn fields_and_files() {
+        let req = request(BODY);
+        let parts: Vec<_> = Multipart::new(&req).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].name(), "title");
+        assert_eq!(parts[0].text(), "Holiday");
+        assert!(!parts[0].is_file());
+
+        assert_eq!(parts[1].filename(), Some("beach \"1\".png"));
+        assert_eq!(parts[1].content_type(), "image/png");
+        assert_eq!(parts[1].header("x-checksum"), Some("abc"));
+        assert_eq!(parts[1].bytes(), b"PNGDATA-PNGDATA");
+    }
+
+    #[test]
+    fn limits() {
+        let req = request(BODY);
+        // The photo is too big on its own, or with the title
+        let mut parts = Multipart::new(&req).unwrap().max_file_size(4);
+        assert!(parts.next().unwrap().is_ok());
+        let error = parts.next().unwrap().unwrap_err();
+        assert_eq!((error.get_status(), error.get_message()), (HttpStatus::PayloadTooLarge, Some("File too large")));
+        assert!(parts.next().is_none());
+        let mut parts = Multipart::new(&req).unwrap().max_total_size(20);
+        assert!(parts.next().unwrap().is_ok());
+        let error = parts.next().unwrap().unwrap_err();
+        assert_eq!((error.get_status(), error.get_message()), (HttpStatus::PayloadTooLarge, Some("Upload too large")));
+        assert!(Multipart::new(&req).unwrap().max_total_size(22).all(|part| part.is_ok()));
+
+        let req = request("--XyZ\nContent-Disposition: form-data; name=\"title\"\n\nno end");
+        let error = Multipart::new(&req).unwrap().next().unwrap().unwrap_err();
+        assert_eq!(error.get_status(), HttpStatus::BadRequest);
+    }
+}
@@ -0,0 +1,165 @@
+// Problem details for HTTP APIs (RFC 7807)
+
+use serde_json::{Map, Value};
+
+use crate::http::error::Error;
+use crate::http::into_response::IntoResponse;
+use crate::http::{response::Response, status::HttpStatus};
+
+const CONTENT_TYPE: &str = "application/problem+json";
+const MEMBERS: [&str; 5] = ["type", "title", "status", "detail", "instance"];
+
+/// An `application/problem+json` error body.
+///
+/// The type defaults to `about:blank` and the title to the reason phrase of the status.
+/// Extension members are serialized next to the standard ones.
+///
+/// # Example
+///
+///
+/// app.post("/transfers", handler(|req, _res| -> Result<String, Problem> {
+///     Err(Problem::new(HttpStatus::Forbidden)
+///         .problem_type("https://example.com/probs/out-of-credit")
+///         .title("You do not have enough credit.")
+///         .detail("Your current balance is 30, but that costs 50.")
+///         .instance(req.get_uri())
+///         .extension("balance", 30))
+/// }));
+///
+#[derive(Debug, Clone)]
+pub struct Problem {
+    problem_type: String,
+    title: String,
+    status: HttpStatus,
+    detail: Option<String>,
+    instance: Option<String>,
+    extensions: Map<String, Value>,
+}
+
+impl Problem {
+    pub fn new(status: HttpStatus) -> Self {
+        Problem {
+            problem_type: "about:blank".to_string(),
+            title: status.reason().to_string(),
+            status,
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        }
+    }
+
+    /// URI identifying the problem type
+    pub fn problem_type(mut self, uri: &str) -> Self {
+        self.problem_type = uri.to_string();
+        self
+    }
+
+    /// Short summary of the problem type
+    pub fn title(mut self, title: &str) -> Self {
+        self.title = title.to_string();
+        self
+    }
+
+    /// Explanation specific to this occurrence of the problem
+    pub fn detail(mut self, detail: &str) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// URI identifying this occurrence of the problem
+    pub fn instance(mut self, uri: &str) -> Self {
+        self.instance = Some(uri.to_string());
+        self
+    }
+
+    /// Add an extension member. Names of the standard members are ignored.
+    pub fn extension<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        if !MEMBERS.contains(&name) {
+            self.extensions.insert(name.to_string(), value.into());
+        }
+        self
+    }
+
+    pub fn get_status(&self) -> HttpStatus {
+        self.status
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut members = Map::new();
+        members.insert("type".to_string(), self.problem_type.clone().into());
+        members.insert("title".to_string(), self.title.clone().into());
+        members.insert("status".to_string(), self.status.code().into());
+        if let Some(detail) = &self.detail {
+            members.insert("detail".to_string(), detail.clone().into());
+        }
+        if let Some(instance) = &self.instance {
+            members.insert("instance".to_string(), instance.clone().into());
+        }
+        members.extend(self.extensions.clone());
+        Value::Object(members)
+    }
+}
+
+/// Server errors keep their message out of the detail, like `Error` does
+impl From<Error> for Problem {
+    fn from(error: Error) -> Self {
+        let problem = Problem::new(error.get_status());
+        match error.get_message() {
+            Some(message) if error.get_status().code() < 500 => problem.detail(message),
+            _ => problem,
+        }
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self, res: &mut Response) {
+        res.set_header("Content-Type", CONTENT_TYPE);
+        res.set_body(self.to_json().to_string().into_bytes());
+        res.set_status(self.status);
+    }
+}
+
+impl From<Problem> for Response {
+    fn from(problem: Problem) -> Self {
+        let mut res = Response::empty();
+        problem.into_response(&mut res);
+        res
+    }
+}
+
+
+#[cfg(test)]
+mod test_problem {
+    use super::Problem;
+    use crate::http::error::Error;
+    use crate::http::{response::Response, status::HttpStatus};
+
+    #[test]
+    fn problem_response() {
+        let problem = Problem::new(HttpStatus::Forbidden)
+            .problem_type("https://example.com/probs/out-of-credit")
+            .detail("Your current balance is 30, but that costs 50.")
+            .extension("balance", 30)
+            .extension("status", 200);
+        let res = Response::from(problem);
+        assert_eq!(res.get_status(), &HttpStatus::Forbidden);
+        assert_eq!(res.get_header("Content-Type").unwrap(), "application/problem+json");
+
+        let body: serde_json::Value = serde_json::from_slice(res.get_body().unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({
+            "type": "https://example.com/probs/out-of-credit",
+            "title": "Forbidden",
+            "status": 403,
+            "detail": "Your current balance is 30, but that costs 50.",
+            "balance": 30,
+        }));
+    }
+
+    #[test]
+    fn from_error() {
+        let problem = Problem::from(Error::bad_request("Missing name")).to_json();
+        assert_eq!(problem["detail"], "Missing name");
+        let problem = Problem::from(Error::internal("database is down")).to_json();
+        assert!(problem.get("detail").is_none());
+    }
+}
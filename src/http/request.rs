@@ -1,8 +1,27 @@
 
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 
+use serde::de::DeserializeOwned;
+#[cfg(feature = "json")]
+use serde_json::Value;
+
+use crate::http::cookie;
 use crate::http::methods::HttpMethod; 
+use crate::http::status::HttpStatus;
+use crate::http::error::Error;
+use crate::http::form::FormData;
+use crate::http::multipart::Multipart;
+use crate::middleware::auth::Principal;
+#[cfg(feature = "json")]
+use crate::middleware::session::Session;
 use crate::utils::parsers;
+use crate::http::extensions::Extensions;
+
+// Largest form body accepted by `form` and `form_as`
+const FORM_LIMIT: usize = 64 * 1024;
 
 // Request structure for handling request in the server api
 
@@ -13,7 +32,22 @@ pub struct Request {
     method: HttpMethod,
     params: HashMap<String, String>,
     body_data: Option<Vec<u8>>,
-    headers: HashMap<String, u8>
+    headers: HashMap<String, String>,
+    remote_addr: Option<SocketAddr>,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    scheme: Option<String>,
+    host: Option<String>,
+    principal: Option<Principal>,
+    #[cfg(feature = "json")]
+    claims: Option<Value>,
+    #[cfg(feature = "json")]
+    session: Option<Session>,
+    csrf_token: Option<String>,
+    route: Option<String>,
+    deadline: Option<(Instant, HttpStatus)>,
+    state: Extensions,
+    extensions: Extensions,
 }
 
 impl std::fmt::Debug for Request {
@@ -38,24 +72,59 @@ impl Request {
             method: HttpMethod::GET,
             params: HashMap::new(),
             body_data: None,
-            headers: HashMap::new()
+            headers: HashMap::new(),
+            remote_addr: None,
+            peer_addr: None,
+            local_addr: None,
+            scheme: None,
+            host: None,
+            principal: None,
+            #[cfg(feature = "json")]
+            claims: None,
+            #[cfg(feature = "json")]
+            session: None,
+            csrf_token: None,
+            route: None,
+            deadline: None,
+            state: Extensions::new(),
+            extensions: Extensions::new(),
         }
     }
 
-    pub fn create(uri: &str,  method: HttpMethod, params: HashMap<String, String>, data: Vec<u8>, headers: HashMap<String, u8>) -> Self {
+    pub fn create(uri: &str,  method: HttpMethod, params: HashMap<String, String>, data: Vec<u8>, headers: HashMap<String, String>) -> Self {
         Request {
             version: "HTTP/1.1".to_string(),
             uri: uri.to_string(), 
             method,
             params,
             body_data: Some(data),
-            headers
+            headers,
+            remote_addr: None,
+            peer_addr: None,
+            local_addr: None,
+            scheme: None,
+            host: None,
+            principal: None,
+            #[cfg(feature = "json")]
+            claims: None,
+            #[cfg(feature = "json")]
+            session: None,
+            csrf_token: None,
+            route: None,
+            deadline: None,
+            state: Extensions::new(),
+            extensions: Extensions::new(),
         }
     }
 
     pub fn new(request: &str) -> Result<Self, std::io::Error> {
+        Request::from_bytes(request.as_bytes())
+    }
+
+    /// Parse a request whose body may not be text, like a file upload
+    pub fn from_bytes(request: &[u8]) -> Result<Self, std::io::Error> {
         let mut req = Request::empty();
-        match parsers::parse_request(request.to_string(), &mut  req) {
+        match parsers::parse_request(request, &mut  req) {
             Ok(_) => Ok(req),
             Err(err) => Err(err)
         }
@@ -74,22 +143,215 @@ impl Request {
         &self.method
     }
 
-    pub fn get_header_field(&self, key: &str) -> Option<&u8> {
+    // Header names are case-insensitive, so the lookup ignores ASCII case
+    pub fn get_header_field(&self, key: &str) -> Option<&String> {
         if key.is_empty() {
             return None;
         }
-        self.headers.get(key)
+        self.headers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    pub fn get_headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Cookies sent by the client in the `Cookie` header
+    pub fn cookies(&self) -> HashMap<String, String> {
+        match self.get_header_field("Cookie") {
+            Some(header) => cookie::parse_cookie_header(header),
+            None => HashMap::new(),
+        }
+    }
+
+    /// Value of a single cookie
+    ///
+    /// # Example
+    ///
+    /// 
+    /// let theme = req.cookie("theme").unwrap_or("light".to_string());
+    /// 
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies().remove(name)
+    }
+
+    /// The media type of `offers` that the client prefers, according to the weights (`q`)
+    /// and wildcards (`text/*`, `*/*`) of its `Accept` header. Ties go to the first offer.
+    /// Without an `Accept` header the client accepts anything and the first offer is returned.
+    /// `None` when the client accepts none of them.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// // Accept: text/html;q=0.9, application/json
+    /// match req.accepts(&["text/html", "application/json"]) {
+    ///     Some("application/json") => res.json_value(&user),
+    ///     Some(_) => res.render_template("user.html", vars),
+    ///     None => res.error(HttpStatus::NotAcceptable),
+    /// };
+    /// 
+    pub fn accepts<'a>(&self, offers: &[&'a str]) -> Option<&'a str> {
+        let ranges = match self.get_header_field("Accept") {
+            Some(accept) if !accept.trim().is_empty() => parsers::parse_accept(accept),
+            _ => return offers.first().copied(),
+        };
+        let mut best: Option<(&str, f32)> = None;
+        for &offer in offers {
+            let media = offer.to_ascii_lowercase();
+            let (kind, _) = media.split_once('/').unwrap_or((&media, ""));
+            // The most specific range that matches the offer sets its weight
+            let weight = ranges.iter()
+                .filter_map(|(range, q)| {
+                    let specificity = match range.as_str() {
+                        "*/*" => 0,
+                        range if range == media => 2,
+                        range if range.strip_suffix("/*") == Some(kind) => 1,
+                        _ => return None,
+                    };
+                    Some((specificity, *q))
+                })
+                .max_by(|a, b| a.0.cmp(&b.0))
+                .map(|(_, q)| q);
+            match weight {
+                Some(q) if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) => best = Some((offer, q)),
+                _ => {},
+            }
+        }
+        best.map(|(offer, _)| offer)
+    }
+
+    /// Language tags of the `Accept-Language` header, lowercase and from the most
+    /// to the least preferred. Languages with `q=0` are left out.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// // Accept-Language: fr-CH, fr;q=0.9, en;q=0.8, de;q=0
+    /// assert_eq!(req.accept_languages(), vec!["fr-ch", "fr", "en"]);
+    /// 
+    pub fn accept_languages(&self) -> Vec<String> {
+        let mut languages = match self.get_header_field("Accept-Language") {
+            Some(header) => parsers::parse_accept(header),
+            None => return Vec::new(),
+        };
+        languages.retain(|(_, q)| *q > 0.0);
+        // Stable, so languages with the same weight keep their order
+        languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+        languages.into_iter().map(|(language, _)| language).collect()
     }
 
     pub fn get_body(&self) -> Option<&Vec<u8>> {
         self.body_data.as_ref()
     }
 
+    // Media type of the body, lowercase and without parameters
+    pub(crate) fn media_type(&self) -> Option<String> {
+        let content_type = self.get_header_field("Content-Type")?;
+        let media_type = content_type.split(';').next().unwrap_or_default();
+        Some(media_type.trim().to_ascii_lowercase())
+    }
+
+    /// Fields of an `application/x-www-form-urlencoded` body, with `+` and `%XX` decoded.
+    /// Fails with `415 Unsupported Media Type` for other content types and with
+    /// `413 Payload Too Large` for bodies over 64 KiB, see `form_with_limit`.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.post("/survey", handler(|req, res| -> Result<String, Error> {
+    ///     let form = req.form()?;
+    ///     let name = form.get("name").unwrap_or("anonymous");
+    ///     let colors = form.get_all("color");
+    ///     Ok(format!("{} likes {}", name, colors.join(", ")))
+    /// }));
+    /// 
+    pub fn form(&self) -> Result<FormData, Error> {
+        self.form_with_limit(FORM_LIMIT)
+    }
+
+    /// Like `form`, with another size limit for the body in bytes
+    pub fn form_with_limit(&self, limit: usize) -> Result<FormData, Error> {
+        let body = self.form_body(limit)?;
+        Ok(FormData::new(parsers::parse_urlencoded(&String::from_utf8_lossy(body))))
+    }
+
+    /// Deserialize an `application/x-www-form-urlencoded` body into a type.
+    /// Fails like `form`, and with `400 Bad Request` when a field is missing or invalid.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// #[derive(Deserialize)]
+    /// struct Login { user: String, password: String, remember: Option<bool> }
+    ///
+    /// let login: Login = req.form_as()?;
+    /// 
+    pub fn form_as<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let body = self.form_body(FORM_LIMIT)?;
+        serde_urlencoded::from_bytes(body).map_err(|e| Error::bad_request(&format!("Invalid form: {}", e)))
+    }
+
+    /// Parts of a `multipart/form-data` body, see `Multipart` for the limits
+    ///
+    /// # Example
+    ///
+    /// 
+    /// for part in req.multipart()? {
+    ///     let part = part?;
+    ///     println!("{} {:?} {} bytes", part.name(), part.filename(), part.size());
+    /// }
+    /// 
+    pub fn multipart(&self) -> Result<Multipart<'_>, Error> {
+        Multipart::new(self)
+    }
+
+    fn form_body(&self, limit: usize) -> Result<&[u8], Error> {
+        if self.media_type().as_deref() != Some("application/x-www-form-urlencoded") {
+            return Err(Error::with_message(HttpStatus::UnsupportedMediaType, "Expected a form body"));
+        }
+        let body = self.get_body().map(Vec::as_slice).unwrap_or_default();
+        if body.len() > limit {
+            return Err(Error::new(HttpStatus::PayloadTooLarge));
+        }
+        Ok(body)
+    }
+
+    /// Deserialize a JSON body.
+    /// Fails with `415 Unsupported Media Type` when the `Content-Type` is not JSON
+    /// (`application/json` or `application/*+json`) and with `400 Bad Request`
+    /// when the body is missing or doesn't match the type.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// #[derive(Deserialize)]
+    /// struct NewUser { name: String, email: String }
+    ///
+    /// app.post("/users", handler(|req, res| -> Result<(), Error> {
+    ///     let user: NewUser = req.json()?;
+    ///     res.json_value(&create_user(user));
+    ///     Ok(())
+    /// }));
+    /// 
+    #[cfg(feature = "json")]
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let is_json = self.media_type()
+            .is_some_and(|media| media == "application/json" || (media.starts_with("application/") && media.ends_with("+json")));
+        if !is_json {
+            return Err(Error::with_message(HttpStatus::UnsupportedMediaType, "Expected a JSON body"));
+        }
+        match self.get_body() {
+            Some(body) if !body.is_empty() => Ok(serde_json::from_slice(body)?),
+            _ => Err(Error::bad_request("Missing JSON body")),
+        }
+    }
+
     pub fn set_body(&mut self, data: Vec<u8>) {
         self.body_data = Some(data);
     }
 
-    pub fn set_headers(&mut self, headers: HashMap<String, u8>) {
+    pub fn set_headers(&mut self, headers: HashMap<String, String>) {
         self.headers = headers;
     }
 
@@ -101,8 +363,114 @@ impl Request {
         self.uri = uri.to_string();
     }
 
-    pub fn set_header_field(&mut self, key: &str, value: u8) {
-        self.headers.insert(key.to_string(), value);
+    pub fn set_header_field(&mut self, key: &str, value: &str) {
+        self.headers.insert(key.to_string(), value.to_string());
+    }
+
+    /// Address of the client, if known.
+    /// It is the peer of the connection unless the peer is a trusted proxy
+    /// (see `App::trusted_proxies`), then it comes from the forwarded headers.
+    /// The port is 0 when the proxy doesn't forward it.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    pub fn set_remote_addr(&mut self, addr: SocketAddr) {
+        self.remote_addr = Some(addr);
+    }
+
+    /// Address of the peer that opened the connection, the proxy when there is one.
+    /// With `App::proxy_protocol`, it is the source address sent by the load balancer.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Address the client connected to.
+    /// With `App::proxy_protocol`, it is the destination address sent by the load balancer.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    pub(crate) fn set_local_addr(&mut self, addr: SocketAddr) {
+        self.local_addr = Some(addr);
+    }
+
+    pub(crate) fn set_peer_addr(&mut self, addr: SocketAddr) {
+        self.peer_addr = Some(addr);
+        self.remote_addr = Some(addr);
+    }
+
+    /// Scheme used by the client, "http" unless a trusted proxy forwarded another one
+    pub fn scheme(&self) -> &str {
+        self.scheme.as_deref().unwrap_or("http")
+    }
+
+    pub fn set_scheme(&mut self, scheme: &str) {
+        self.scheme = Some(scheme.to_string());
+    }
+
+    /// Host requested by the client: the host forwarded by a trusted proxy,
+    /// or the `Host` header
+    pub fn host(&self) -> Option<&str> {
+        match &self.host {
+            Some(host) => Some(host),
+            None => self.get_header_field("Host").map(String::as_str),
+        }
+    }
+
+    pub fn set_host(&mut self, host: &str) {
+        self.host = Some(host.to_string());
+    }
+
+    /// Principal authenticated by an auth guard, if any
+    pub fn get_principal(&self) -> Option<&Principal> {
+        self.principal.as_ref()
+    }
+
+    pub fn set_principal(&mut self, principal: Principal) {
+        self.principal = Some(principal);
+    }
+
+    /// Claims of the verified JWT deserialized into `T`.
+    /// Returns `None` if there is no token or the claims do not match `T`.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// #[derive(Deserialize)]
+    /// struct Claims { sub: String, scope: String }
+    ///
+    /// let claims = req.claims::<Claims>().unwrap();
+    /// 
+    #[cfg(feature = "json")]
+    pub fn claims<T: DeserializeOwned>(&self) -> Option<T> {
+        let claims = self.claims.clone()?;
+        serde_json::from_value(claims).ok()
+    }
+
+    #[cfg(feature = "json")]
+    pub fn set_claims(&mut self, claims: Value) {
+        self.claims = Some(claims);
+    }
+
+    /// Session loaded by the `Sessions` middleware, if the route uses it
+    #[cfg(feature = "json")]
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    #[cfg(feature = "json")]
+    pub fn set_session(&mut self, session: Session) {
+        self.session = Some(session);
+    }
+
+    /// CSRF token of the client, set by the `Csrf` middleware
+    pub fn csrf_token(&self) -> Option<&str> {
+        self.csrf_token.as_deref()
+    }
+
+    pub fn set_csrf_token(&mut self, token: &str) {
+        self.csrf_token = Some(token.to_string());
     }
 
     pub fn get_params(&self) -> &HashMap<String, String> {
@@ -116,4 +484,53 @@ impl Request {
     pub fn get_param(&self, key: &str) -> Option<&String> {
         self.params.get(key)
     }
+
+    /// Pattern of the route that matched the request (e.g. `/users/:id`)
+    pub fn get_route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+
+    pub fn set_route(&mut self, route: &str) {
+        self.route = Some(route.to_string());
+    }
+
+    /// Instant after which the `Timeout` layer cancels the async handler of the request
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline.map(|(deadline, _)| deadline)
+    }
+
+    // Status of the response sent when the deadline passes
+    pub(crate) fn set_deadline(&mut self, deadline: Instant, status: HttpStatus) {
+        self.deadline = Some((deadline, status));
+    }
+
+    pub(crate) fn timeout_status(&self) -> Option<HttpStatus> {
+        self.deadline.map(|(_, status)| status)
+    }
+
+    /// Shared state of the given type, set with `App::with_state` or `Router::with_state`.
+    /// Available to the layers and handlers of the app and of the router handling the request.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// let config = req.state::<Config>().unwrap();
+    /// 
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.state.get_arc()
+    }
+
+    // State of the app and of the router handling the request
+    pub(crate) fn add_state(&mut self, state: &Extensions) {
+        self.state.extend(state);
+    }
+
+    /// Values added by the layers for the handlers, see `Extensions`
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
 }
@@ -3,17 +3,30 @@
 use std::collections::HashMap;
 use std::fmt;
 use std::io::Read;
+use std::time::SystemTime;
 
+use crate::http::cookie::Cookie;
+use crate::http::extensions::Extensions;
+use crate::http::headers::Headers;
+use crate::http::request::Request;
 use crate::http::status::HttpStatus;
+use crate::middleware::i18n::Translator;
 use crate::utils::formatter;
 
+// Media type and the function building its representation, for `Response::format`
+type Format<'f> = (&'f str, &'f dyn for<'a> Fn(&'a mut Response) -> &'a mut Response);
+
 // Response structure for handling response in the server api
 
 pub struct Response {
     version: String,
     status: HttpStatus,
-    headers: HashMap<String, String>,
+    headers: Headers,
     response_body: Option<Vec<u8>>,
+    template_vars: HashMap<String, String>,
+    default_error: bool,
+    error_detail: Option<String>,
+    extensions: Extensions,
 }
 
 
@@ -45,8 +58,30 @@ impl Response {
     /// 
     pub fn json(&mut self, body: &str) -> &mut Self {
         self.headers.insert("Content-Type".to_string(), "application/json".to_string());
-        self.response_body = Some(body.into());
-        self
+        self.set_body(body.into())
+    }
+
+    /// Serialize a value as the JSON body of the response.
+    /// The status is set to `500 Internal Server Error` if it can't be serialized.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// #[derive(Serialize)]
+    /// struct User { id: u32, name: String }
+    ///
+    /// res.json_value(&User { id: 1, name: "Ana".to_string() });
+    /// 
+    #[cfg(feature = "json")]
+    pub fn json_value<T: serde::Serialize>(&mut self, value: &T) -> &mut Self {
+        match serde_json::to_string(value) {
+            Ok(body) => self.json(&body),
+            Err(e) => {
+                self.status = HttpStatus::InternalServerError;
+                println!("Error: {}", e);
+                self
+            }
+        }
     }
 
     /// Render an HTML template
@@ -65,23 +100,23 @@ impl Response {
     pub fn render_template(&mut self, file_path: &str, params: HashMap<String, String>) -> &mut Self {
         self.headers.insert("Content-Type".to_string(), "text/html".to_string());
 
-        match formatter::format_html(file_path, params) {
-            Ok(body) => {
-                self.response_body = Some(body.into());
-                return self;
-            },
+        // Messages of the I18n layer, if any
+        let translator = self.extensions.get::<Translator>();
+        let translate = |key: &str| translator.map_or(key.to_string(), |t| t.t(key));
+        match formatter::format_html(file_path, params, &self.template_vars, &translate) {
+            Ok(body) => self.set_body(body.into()),
             Err(e) => {
                 self.status = HttpStatus::InternalServerError;
                 println!("Error: {}", e);
-                return self;
+                self
             }
         }
     }
 
     pub fn send_file(&mut self, file_path: &str) -> &mut Self {
 
-        let extension = file_path.split('.').last().unwrap();
-        let file_name = file_path.split('/').last().unwrap();
+        let extension = file_path.split('.').next_back().unwrap();
+        let file_name = file_path.split('/').next_back().unwrap();
         match extension {
             "html" => self.headers.insert("Content-Type".to_string(), "text/html".to_string()),
             "png" => self.headers.insert("Content-Type".to_string(), "image/png".to_string()),
@@ -106,7 +141,7 @@ impl Response {
                     self.status = HttpStatus::InternalServerError;
                     println!("Error reading file: {}", e);
                 }
-                self.response_body = Some(body);
+                self.set_body(body);
             }
             Err(e) => {
                 self.status = HttpStatus::InternalServerError;
@@ -121,8 +156,12 @@ impl Response {
         Response {
             version: "HTTP/1.1".to_string(),
             status: HttpStatus::NotFound,
-            headers: HashMap::new(),
-            response_body: Some(Vec::new())
+            headers: Headers::new(),
+            response_body: Some(Vec::new()),
+            template_vars: HashMap::new(),
+            default_error: false,
+            error_detail: None,
+            extensions: Extensions::new(),
         }
     }
     
@@ -130,8 +169,12 @@ impl Response {
         Response {
             version: "HTTP/1.1".to_string(),
             status: status_code,
-            headers,
-            response_body: Some(body)
+            headers: headers.into(),
+            response_body: Some(body),
+            template_vars: HashMap::new(),
+            default_error: false,
+            error_detail: None,
+            extensions: Extensions::new(),
         }
     }
 
@@ -153,20 +196,251 @@ impl Response {
 
     pub fn set_body(&mut self, body: Vec<u8>) -> &mut Self {
         self.response_body = Some(body);
+        self.default_error = false;
+        self.error_detail = None;
+        self
+    }
+
+    /// Turn the response into a plain text error response for the status.
+    /// The body is replaced by the handler registered with `App::on_error`
+    /// for the status, if any, while the headers are kept.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.get("/admin", |req, res| {
+    ///     if req.get_principal().is_none() {
+    ///         return res.error(HttpStatus::Forbidden);
+    ///     }
+    ///     res.send("Welcome")
+    /// });
+    /// 
+    pub fn error(&mut self, status: HttpStatus) -> &mut Self {
+        self.status = status;
+        self.send(status.reason());
+        self.default_error = true;
+        self
+    }
+
+    /// Like `error`, with a message explaining this occurrence of the error as the body.
+    /// The error handlers still replace it and can read it with `get_error_detail`,
+    /// with problem details it becomes the `detail` member.
+    pub fn error_with_detail(&mut self, status: HttpStatus, detail: &str) -> &mut Self {
+        self.error(status);
+        self.response_body = Some(detail.as_bytes().to_vec());
+        self.error_detail = Some(detail.to_string());
+        self
+    }
+
+    /// Message given to `error_with_detail`, while the error response is the default one
+    pub fn get_error_detail(&self) -> Option<&str> {
+        self.error_detail.as_deref()
+    }
+
+    /// Build the response with the function of the media type the client prefers,
+    /// chosen with `req.accepts`. Answers `406 Not Acceptable` when the client accepts
+    /// none of them. `Vary: Accept` is added so caches keep each representation apart.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.get("/users/:id", |req, res| {
+    ///     let user = find_user(req.get_param("id").unwrap());
+    ///     res.format(req, &[
+    ///         ("application/json", &|res| res.json_value(&user)),
+    ///         ("text/html", &|res| res.render_template("user.html", user.vars())),
+    ///     ])
+    /// });
+    /// 
+    pub fn format(&mut self, req: &Request, formats: &[Format<'_>]) -> &mut Self {
+        self.headers.append("Vary".to_string(), "Accept".to_string());
+        let offers: Vec<&str> = formats.iter().map(|(media, _)| *media).collect();
+        match req.accepts(&offers) {
+            Some(media) => {
+                let (_, build) = formats.iter().find(|(offer, _)| *offer == media).unwrap();
+                build(self)
+            },
+            None => self.error(HttpStatus::NotAcceptable),
+        }
+    }
+
+    // Drop what a handler built: the status, the body and the headers describing it.
+    // The other headers, set by the layers (security headers, cookies...), are kept.
+    pub(crate) fn clear_content(&mut self) {
+        for name in ["Content-Type", "Content-Length", "Content-Disposition", "Content-Encoding", "Content-Range"] {
+            self.headers.remove(name);
+        }
+        self.status = HttpStatus::Ok;
+        self.response_body = Some(Vec::new());
+        self.default_error = false;
+        self.error_detail = None;
+    }
+
+    /// Whether the body is still the default one set by `error`
+    pub fn is_default_error(&self) -> bool {
+        self.default_error
+    }
+
+    pub fn get_header(&self, key: &str) -> Option<&String> {
+        self.headers.get(key)
+    }
+
+    /// Every value of a header that can be repeated, like `Set-Cookie`
+    pub fn get_header_values(&self, key: &str) -> Vec<&String> {
+        self.headers.get_all(key)
+    }
+
+    /// Set a header, replacing any previous value.
+    /// Panics on names that aren't valid and values with line breaks, use
+    /// `try_set_header` for values that come from the request or other input.
+    pub fn set_header(&mut self, key: &str, value: &str) -> &mut Self {
+        self.headers.insert(key.to_string(), value.to_string());
         self
     }
+
+    /// Set a header, failing instead of panicking on invalid names and values
+    pub fn try_set_header(&mut self, key: &str, value: &str) -> Result<&mut Self, std::io::Error> {
+        self.headers.try_insert(key.to_string(), value.to_string())?;
+        Ok(self)
+    }
+
+    /// Add a value to a header, keeping the previous ones. Panics like `set_header`.
+    pub fn append_header(&mut self, key: &str, value: &str) -> &mut Self {
+        self.headers.append(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Add a value to a header, failing instead of panicking on invalid names and values
+    pub fn try_append_header(&mut self, key: &str, value: &str) -> Result<&mut Self, std::io::Error> {
+        self.headers.try_append(key.to_string(), value.to_string())?;
+        Ok(self)
+    }
+
+    pub fn remove_header(&mut self, key: &str) -> Option<String> {
+        self.headers.remove(key)
+    }
+
+    /// Set a variable available to every template rendered with this response.
+    /// Middleware uses it to expose values like `{{csrf_token}}` to the templates.
+    /// Unlike the params of `render_template`, the template doesn't need to use it.
+    pub fn set_template_var(&mut self, name: &str, value: &str) -> &mut Self {
+        self.template_vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn get_template_var(&self, name: &str) -> Option<&String> {
+        self.template_vars.get(name)
+    }
+
+    /// Add a `Set-Cookie` header for the cookie
+    ///
+    /// # Example
+    ///
+    /// 
+    /// res.set_cookie(Cookie::new("theme", "dark").path("/").http_only(true));
+    /// 
+    pub fn set_cookie(&mut self, cookie: Cookie) -> &mut Self {
+        self.headers.append("Set-Cookie".to_string(), cookie.to_string());
+        self
+    }
+
+    // Take the status and body of another response. Its headers replace the
+    // ones with the same name, the other headers set by the layers are kept.
+    pub(crate) fn merge(&mut self, other: Response) {
+        self.status = other.status;
+        self.response_body = other.response_body;
+        self.default_error = other.default_error;
+        self.error_detail = other.error_detail;
+        for (name, _) in other.headers.iter() {
+            self.headers.remove(name);
+        }
+        for (name, value) in other.headers.iter() {
+            self.headers.append(name.clone(), value.clone());
+        }
+        self.template_vars.extend(other.template_vars);
+        self.extensions.extend(&other.extensions);
+    }
+
+    /// Values added by the handler for the layers, see `Extensions`
+    ///
+    /// # Example
+    ///
+    /// 
+    /// // In the handler
+    /// res.extensions_mut().insert(CacheFor(Duration::from_secs(60)));
+    ///
+    /// // In a layer, after next.run(req, res)
+    /// if let Some(cache) = res.extensions().get::<CacheFor>() {
+    ///     res.set_header("Cache-Control", &format!("max-age={}", cache.0.as_secs()));
+    /// }
+    /// 
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Serialize the response as sent on the wire: status line, headers and the raw body.
+    ///
+    /// `Content-Length` is always set to the length of the body, replacing the one set
+    /// by the handler, and `Transfer-Encoding` is dropped as the body is sent whole.
+    /// Responses that can't have a body (1xx, 204 and 304) are sent without a body
+    /// or a `Content-Length`. `Date` is added when the response has none.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// res.send_file("image.png");
+    /// stream.write_all(&res.to_bytes()).await?;
+    /// 
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let bodiless = self.status.is_bodiless();
+        let body = match bodiless {
+            true => &[],
+            false => self.response_body.as_deref().unwrap_or_default(),
+        };
+        let mut head = format!("{} {}\r\n", self.version, self.status);
+        for (key, value) in self.headers.iter() {
+            if key.eq_ignore_ascii_case("Content-Length") || key.eq_ignore_ascii_case("Transfer-Encoding") {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        if self.headers.get("Date").is_none() {
+            head.push_str(&format!("Date: {}\r\n", httpdate::fmt_http_date(SystemTime::now())));
+        }
+        if !bodiless {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    /// Ask the client to delete a cookie set with `Path=/`.
+    /// Cookies with another path or domain must be removed with `set_cookie`
+    /// and an expired cookie with the same attributes.
+    pub fn remove_cookie(&mut self, name: &str) -> &mut Self {
+        self.set_cookie(Cookie::removal(name))
+    }
 }
 
+// Readable form of the response for logs, bodies that aren't UTF-8 are not kept as they are.
+// Use `to_bytes` to send it.
 impl fmt::Display for Response {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} {}\r\n",self.version, self.status)?;
-        for (key, value) in &self.headers {
+        for (key, value) in self.headers.iter() {
             write!(f, "{}: {}\r\n", key, value)?;
         }
         write!(f, "\r\n")?;
         match &self.response_body {
             Some(body) => {
-                let body = String::from_utf8_lossy(&body);
+                let body = String::from_utf8_lossy(body);
                 write!(f, "{}", body)
             },
             None => write!(f, "")
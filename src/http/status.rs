@@ -1,23 +1,65 @@
 use std::fmt;
 use std::cmp;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum HttpStatus {
     Ok,
+    NoContent,
+    NotModified,
     BadRequest,
+    Unauthorized,
+    Forbidden,
     NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    TooManyRequests,
     InternalServerError,
+    ServiceUnavailable,
+    GatewayTimeout,
 }
 
 impl HttpStatus{
     fn as_str(&self) -> &'static str {
         match self {
             HttpStatus::Ok => "200 OK",
+            HttpStatus::NoContent => "204 No Content",
+            HttpStatus::NotModified => "304 Not Modified",
             HttpStatus::BadRequest => "400 Bad Request",
+            HttpStatus::Unauthorized => "401 Unauthorized",
+            HttpStatus::Forbidden => "403 Forbidden",
             HttpStatus::NotFound => "404 Not Found",
+            HttpStatus::MethodNotAllowed => "405 Method Not Allowed",
+            HttpStatus::NotAcceptable => "406 Not Acceptable",
+            HttpStatus::PayloadTooLarge => "413 Payload Too Large",
+            HttpStatus::UnsupportedMediaType => "415 Unsupported Media Type",
+            HttpStatus::TooManyRequests => "429 Too Many Requests",
             HttpStatus::InternalServerError => "500 Internal Server Error",
+            HttpStatus::ServiceUnavailable => "503 Service Unavailable",
+            HttpStatus::GatewayTimeout => "504 Gateway Timeout",
         }
     }
+
+    /// Numeric status code, e.g. 404
+    pub fn code(&self) -> u16 {
+        self.as_str()[..3].parse().unwrap()
+    }
+
+    /// Reason phrase, e.g. "Not Found"
+    pub fn reason(&self) -> &'static str {
+        &self.as_str()[4..]
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.code() >= 400
+    }
+
+    /// Whether responses with the status never have a body (1xx, 204 and 304)
+    pub fn is_bodiless(&self) -> bool {
+        let code = self.code();
+        code < 200 || code == 204 || code == 304
+    }
 }
 
 impl fmt::Display for HttpStatus {
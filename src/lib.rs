@@ -4,10 +4,38 @@ mod routing;
 mod utils;
 mod http;
 mod server;
+mod middleware;
 
 pub mod config;
 
 pub use server::app::App;
-pub use http::{request::Request,response::Response};
-pub use routing::router::Router;
+pub use http::{request::Request,response::Response,status::HttpStatus};
+pub use http::cookie::{Cookie,SameSite};
+pub use http::cookie_jar::CookieJar;
+pub use http::error::Error;
+#[cfg(feature = "json")]
+pub use http::problem::Problem;
+pub use http::form::FormData;
+pub use http::extensions::Extensions;
+pub use http::forwarded::ProxyHeader;
+pub use http::multipart::{Multipart,Part};
+pub use http::into_response::{IntoResponse,handler,async_handler};
+pub use http::extract::{extract,ExtractHandler,FromRequest,Path,Query,Form,Header,State,TypedHeader};
+pub use http::extract::{Authorization,ContentLength,ContentType,UserAgent};
+#[cfg(feature = "json")]
+pub use http::extract::Json;
+pub use routing::{router::Router,route::Route};
+pub use middleware::{Middleware,Next,rate_limit::RateLimit};
+pub use middleware::auth::{BasicAuth,BearerAuth,CredentialVerifier,Principal};
+#[cfg(feature = "json")]
+pub use middleware::jwt::{JwtAuth,KeySet};
+#[cfg(feature = "json")]
+pub use middleware::session::{Session,Sessions};
+#[cfg(feature = "json")]
+pub use middleware::session_store::{FileStore,MemoryStore,SessionData,SessionStore};
+pub use middleware::csrf::Csrf;
+pub use middleware::security_headers::SecurityHeaders;
+pub use middleware::timeout::Timeout;
+pub use middleware::ip_filter::IpFilter;
+pub use middleware::i18n::{Catalogs,I18n,Translator};
 
@@ -0,0 +1,215 @@
+// Authentication guards for the Authorization header
+
+use std::sync::Arc;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::http::{request::Request, response::Response, status::HttpStatus};
+use crate::middleware::{Middleware, Next};
+
+/// The authenticated identity of a request.
+/// Guards store it in the request so handlers can read it with `req.get_principal()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    id: String,
+    scheme: String,
+}
+
+impl Principal {
+    pub fn new(id: &str, scheme: &str) -> Self {
+        Principal {
+            id: id.to_string(),
+            scheme: scheme.to_string(),
+        }
+    }
+
+    /// Identifier of the principal (user name, client id, subject...)
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    /// Authentication scheme used to authenticate the principal
+    pub fn get_scheme(&self) -> &str {
+        &self.scheme
+    }
+}
+
+/// Checks the user name and password sent with Basic authentication
+pub trait CredentialVerifier: Send + Sync {
+    fn verify(&self, username: &str, password: &str) -> bool;
+}
+
+impl<F> CredentialVerifier for F
+    where F: Fn(&str, &str) -> bool + Send + Sync
+{
+    fn verify(&self, username: &str, password: &str) -> bool {
+        self(username, password)
+    }
+}
+
+type TokenValidator = Arc<dyn Fn(&str) -> Option<Principal> + Send + Sync>;
+
+// Split an Authorization header into its scheme and credentials
+pub(crate) fn credentials<'a>(req: &'a Request, scheme: &str) -> Option<&'a str> {
+    let header = req.get_header_field("Authorization")?;
+    let (name, value) = header.trim().split_once(' ')?;
+    match name.eq_ignore_ascii_case(scheme) {
+        true => Some(value.trim()),
+        false => None,
+    }
+}
+
+pub(crate) fn unauthorized(res: &mut Response, challenge: &str) {
+    res.error(HttpStatus::Unauthorized);
+    res.set_header("WWW-Authenticate", challenge);
+}
+
+/// HTTP Basic authentication guard (RFC 7617)
+///
+/// # Example
+///
+///
+/// let admin = app.router("admin");
+/// admin.layer(BasicAuth::new("admin", |user: &str, password: &str| {
+///     user == "admin" && password == "secret"
+/// }));
+///
+pub struct BasicAuth {
+    realm: String,
+    verifier: Box<dyn CredentialVerifier>,
+}
+
+impl BasicAuth {
+    pub fn new<V: CredentialVerifier + 'static>(realm: &str, verifier: V) -> Self {
+        BasicAuth {
+            realm: realm.to_string(),
+            verifier: Box::new(verifier),
+        }
+    }
+
+    fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm)
+    }
+
+    fn authenticate(&self, req: &Request) -> Option<Principal> {
+        let encoded = credentials(req, "Basic")?;
+        let decoded = STANDARD.decode(encoded).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (username, password) = decoded.split_once(':')?;
+        match self.verifier.verify(username, password) {
+            true => Some(Principal::new(username, "Basic")),
+            false => None,
+        }
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next<'_>) {
+        match self.authenticate(req) {
+            Some(principal) => {
+                req.set_principal(principal);
+                next.run(req, res);
+            },
+            None => unauthorized(res, &self.challenge()),
+        }
+    }
+}
+
+/// HTTP Bearer token guard (RFC 6750)
+///
+/// The validator receives the token and returns the principal it belongs to,
+/// or `None` if the token is not valid.
+///
+/// # Example
+///
+///
+/// app.layer(BearerAuth::new("api", |token| match token {
+///     "secret-token" => Some(Principal::new("service", "Bearer")),
+///     _ => None,
+/// }));
+///
+pub struct BearerAuth {
+    realm: String,
+    validator: TokenValidator,
+}
+
+impl BearerAuth {
+    pub fn new<F>(realm: &str, validator: F) -> Self
+        where F: Fn(&str) -> Option<Principal> + Send + Sync + 'static
+    {
+        BearerAuth {
+            realm: realm.to_string(),
+            validator: Arc::new(validator),
+        }
+    }
+}
+
+impl Middleware for BearerAuth {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next<'_>) {
+        let token = match credentials(req, "Bearer") {
+            Some(token) if !token.is_empty() => token.to_string(),
+            // No credentials: the challenge must not carry an error code
+            _ => return unauthorized(res, &format!("Bearer realm=\"{}\"", self.realm)),
+        };
+
+        match (self.validator)(&token) {
+            Some(principal) => {
+                req.set_principal(principal);
+                next.run(req, res);
+            },
+            None => unauthorized(res, &format!("Bearer realm=\"{}\", error=\"invalid_token\"", self.realm)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_auth {
+    use super::{BasicAuth, BearerAuth, Principal};
+    use crate::http::{request::Request, response::Response};
+    use crate::middleware::{Middleware, Next};
+
+    fn run(layer: &dyn Middleware, authorization: Option<&str>) -> (Request, Response) {
+        let mut req = Request::new("GET /admin HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        if let Some(value) = authorization {
+            req.set_header_field("Authorization", value);
+        }
+        let mut res = Response::empty();
+        let endpoint = |_req: &mut Request, res: &mut Response| res.set_status(crate::http::status::HttpStatus::Ok);
+        layer.handle(&mut req, &mut res, Next::new(&[], &endpoint));
+        (req, res)
+    }
+
+    #[test]
+    fn basic_auth() {
+        let guard = BasicAuth::new("admin", |user: &str, password: &str| user == "aladdin" && password == "open sesame");
+
+        let (req, res) = run(&guard, Some("Basic YWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+        assert_eq!(res.get_status().to_string(), "200 OK");
+        assert_eq!(req.get_principal(), Some(&Principal::new("aladdin", "Basic")));
+
+        let (_, res) = run(&guard, Some("Basic YWxhZGRpbjp3cm9uZw=="));
+        assert_eq!(res.get_status().to_string(), "401 Unauthorized");
+        assert_eq!(res.get_header("WWW-Authenticate").unwrap(), "Basic realm=\"admin\", charset=\"UTF-8\"");
+    }
+
+    #[test]
+    fn bearer_auth() {
+        let guard = BearerAuth::new("api", |token| match token {
+            "abc" => Some(Principal::new("service", "Bearer")),
+            _ => None,
+        });
+
+        let (req, res) = run(&guard, Some("bearer abc"));
+        assert_eq!(res.get_status().to_string(), "200 OK");
+        assert_eq!(req.get_principal().unwrap().get_id(), "service");
+
+        let (_, res) = run(&guard, None);
+        assert_eq!(res.get_header("WWW-Authenticate").unwrap(), "Bearer realm=\"api\"");
+
+        let (_, res) = run(&guard, Some("Bearer nope"));
+        assert_eq!(res.get_status().to_string(), "401 Unauthorized");
+        assert_eq!(res.get_header("WWW-Authenticate").unwrap(), "Bearer realm=\"api\", error=\"invalid_token\"");
+    }
+}
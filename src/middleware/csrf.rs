@@ -0,0 +1,225 @@
+// CSRF protection for routes that change state
+
+use crate::http::cookie::{Cookie, SameSite};
+use crate::http::methods::HttpMethod;
+use crate::http::{request::Request, response::Response, status::HttpStatus};
+use crate::middleware::{Middleware, Next};
+use crate::utils::{parsers, random};
+
+const FORM_FIELD: &str = "csrf_token";
+const HEADER: &str = "X-CSRF-Token";
+#[cfg(feature = "json")]
+const SESSION_KEY: &str = "_csrf_token";
+
+enum Strategy {
+    #[cfg(feature = "json")]
+    Synchronizer,
+    DoubleSubmit { cookie_name: String },
+}
+
+/// CSRF protection layer.
+///
+/// Every request gets a token, available with `req.csrf_token()` and in the templates
+/// rendered by the handler as `{{csrf_token}}`. Requests with an unsafe method
+/// (POST, PUT, PATCH, DELETE) must send it back in the `csrf_token` field of a form
+/// (urlencoded or multipart) or in the `X-CSRF-Token` header, otherwise they are
+/// answered with `403 Forbidden`.
+///
+/// # Example
+///
+///
+/// // Token kept in the session, needs the Sessions layer first (and the `json` feature)
+/// app.layer(Sessions::new(MemoryStore::new()));
+/// app.layer(Csrf::synchronizer());
+///
+/// // Or without sessions, with the token in a cookie
+/// app.layer(Csrf::double_submit());
+///
+/// // <form method="post"><input type="hidden" name="csrf_token" value="{{csrf_token}}"></form>
+///
+pub struct Csrf {
+    strategy: Strategy,
+    secure: bool,
+}
+
+impl Csrf {
+    /// Store the token in the session (synchronizer token pattern)
+    #[cfg(feature = "json")]
+    pub fn synchronizer() -> Self {
+        Csrf { strategy: Strategy::Synchronizer, secure: true }
+    }
+
+    /// Store the token in a cookie that the client must echo (double-submit cookie pattern)
+    pub fn double_submit() -> Self {
+        Csrf {
+            strategy: Strategy::DoubleSubmit { cookie_name: "csrf_token".to_string() },
+            secure: true,
+        }
+    }
+
+    /// Name of the double-submit cookie ("csrf_token" by default)
+    #[cfg_attr(not(feature = "json"), allow(irrefutable_let_patterns))]
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        if let Strategy::DoubleSubmit { cookie_name } = &mut self.strategy {
+            *cookie_name = name.to_string();
+        }
+        self
+    }
+
+    /// Send the double-submit cookie only over HTTPS (enabled by default)
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    // Current token of the client, and whether it has just been created
+    fn token(&self, req: &Request) -> Result<(String, bool), &'static str> {
+        let existing = match &self.strategy {
+            #[cfg(feature = "json")]
+            Strategy::Synchronizer => {
+                let session = req.session().ok_or("CSRF synchronizer tokens need the Sessions layer")?;
+                session.get::<String>(SESSION_KEY)
+            },
+            Strategy::DoubleSubmit { cookie_name } => req.cookie(cookie_name),
+        };
+        match existing {
+            Some(token) if !token.is_empty() => Ok((token, false)),
+            _ => Ok((random::token(32), true)),
+        }
+    }
+
+    #[cfg_attr(not(feature = "json"), allow(unused_variables))]
+    fn store(&self, req: &Request, res: &mut Response, token: &str) {
+        match &self.strategy {
+            #[cfg(feature = "json")]
+            Strategy::Synchronizer => {
+                if let Some(session) = req.session() {
+                    session.set(SESSION_KEY, token);
+                }
+            },
+            Strategy::DoubleSubmit { cookie_name } => {
+                res.set_cookie(Cookie::new(cookie_name, token)
+                    .path("/")
+                    .secure(self.secure)
+                    .same_site(SameSite::Strict));
+            },
+        }
+    }
+}
+
+fn is_unsafe(method: &HttpMethod) -> bool {
+    matches!(method, HttpMethod::POST | HttpMethod::PUT | HttpMethod::PATCH | HttpMethod::DELETE)
+}
+
+// Token sent back by the client in the header or the form body
+fn submitted_token(req: &Request) -> Option<String> {
+    if let Some(token) = req.get_header_field(HEADER) {
+        return Some(token.to_string());
+    }
+    match req.media_type()?.as_str() {
+        "application/x-www-form-urlencoded" => {
+            let body = String::from_utf8_lossy(req.get_body()?).to_string();
+            parsers::parse_urlencoded(&body).into_iter()
+                .find(|(name, _)| name == FORM_FIELD)
+                .map(|(_, value)| value)
+        },
+        // Upload forms, the token is one of their text fields
+        "multipart/form-data" => req.multipart().ok()?
+            .map_while(Result::ok)
+            .find(|part| part.name() == FORM_FIELD && !part.is_file())
+            .map(|part| part.text()),
+        _ => None,
+    }
+}
+
+// Compare without leaking the position of the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl Middleware for Csrf {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next<'_>) {
+        let (token, created) = match self.token(req) {
+            Ok(token) => token,
+            Err(e) => {
+                println!("Error: {}", e);
+                res.error(HttpStatus::InternalServerError);
+                return;
+            }
+        };
+
+        if is_unsafe(req.get_method()) {
+            let valid = !created && submitted_token(req)
+                .is_some_and(|submitted| constant_time_eq(submitted.as_bytes(), token.as_bytes()));
+            if !valid {
+                println!("Rejected request without a valid CSRF token on {}", req.get_uri());
+                res.error(HttpStatus::Forbidden);
+                return;
+            }
+        }
+
+        if created {
+            self.store(req, res, &token);
+        }
+        req.set_csrf_token(&token);
+        res.set_template_var("csrf_token", &token);
+        next.run(req, res);
+    }
+}
+
+
+#[cfg(test)]
+mod test_csrf {
+    use super::Csrf;
+    use crate::http::{request::Request, response::Response, status::HttpStatus};
+    use crate::middleware::{Middleware, Next};
+
+    fn run(layer: &Csrf, request: &str) -> Response {
+        let mut req = Request::new(request).unwrap();
+        let mut res = Response::empty();
+        let endpoint = |req: &mut Request, res: &mut Response| {
+            assert!(req.csrf_token().is_some());
+            res.set_status(HttpStatus::Ok);
+        };
+        layer.handle(&mut req, &mut res, Next::new(&[], &endpoint));
+        res
+    }
+
+    #[test]
+    fn double_submit() {
+        let layer = Csrf::double_submit();
+
+        // Safe requests get a token
+        let res = run(&layer, "GET /form HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let cookie = res.get_header("Set-Cookie").unwrap();
+        assert!(cookie.starts_with("csrf_token="));
+        let token = res.get_template_var("csrf_token").unwrap().clone();
+
+        let res = run(&layer, "POST /form HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(res.get_status(), &HttpStatus::Forbidden);
+
+        let request = format!("POST /form HTTP/1.1\r\nCookie: csrf_token={0}\r\nX-CSRF-Token: {0}\r\n\r\n", token);
+        assert_eq!(run(&layer, &request).get_status(), &HttpStatus::Ok);
+
+        let request = format!("POST /form HTTP/1.1\r\nCookie: csrf_token={}\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nname=ana&csrf_token={}", token, token);
+        assert_eq!(run(&layer, &request).get_status(), &HttpStatus::Ok);
+
+        let multipart = |value: &str| format!(
+            "POST /upload HTTP/1.1\r\nCookie: csrf_token={}\r\nContent-Type: multipart/form-data; boundary=XyZ\r\n\r\n\
+             --XyZ\r\nContent-Disposition: form-data; name=\"photo\"; filename=\"a.png\"\r\n\r\nPNG\r\n\
+             --XyZ\r\nContent-Disposition: form-data; name=\"csrf_token\"\r\n\r\n{}\r\n--XyZ--\r\n",
+            token, value);
+        assert_eq!(run(&layer, &multipart(&token)).get_status(), &HttpStatus::Ok);
+        assert_eq!(run(&layer, &multipart("forged")).get_status(), &HttpStatus::Forbidden);
+
+        let request = format!("POST /form HTTP/1.1\r\nCookie: csrf_token={}\r\nX-CSRF-Token: forged\r\n\r\n", token);
+        assert_eq!(run(&layer, &request).get_status(), &HttpStatus::Forbidden);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn synchronizer_needs_sessions() {
+        let res = run(&Csrf::synchronizer(), "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!(res.get_status(), &HttpStatus::InternalServerError);
+    }
+}
@@ -0,0 +1,311 @@
+// Locale resolution and translated messages
+
+use std::collections::HashMap;
+#[cfg(feature = "json")]
+use std::fs;
+#[cfg(feature = "json")]
+use std::io::{Error, ErrorKind};
+#[cfg(feature = "json")]
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::http::{request::Request, response::Response};
+use crate::middleware::{Middleware, Next};
+use crate::utils::parsers;
+
+/// Translated messages, one catalog of `key -> message` per locale.
+///
+/// # Example
+///
+///
+/// // locales/en.json: {"greeting": "Hello", "farewell": "Bye"}
+/// // locales/es.json: {"greeting": "Hola"}
+/// let catalogs = Catalogs::load_dir("locales")?;
+///
+#[derive(Debug, Clone, Default)]
+pub struct Catalogs {
+    catalogs: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalogs {
+    pub fn new() -> Self {
+        Catalogs { catalogs: HashMap::new() }
+    }
+
+    /// Load every `<locale>.json` file of a directory. Each file holds a flat
+    /// JSON object whose values are the messages.
+    #[cfg(feature = "json")]
+    pub fn load_dir(dir: &str) -> Result<Self, Error> {
+        let mut catalogs = Catalogs::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "json") {
+                let locale = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+                catalogs.load_file(&locale, &path)?;
+            }
+        }
+        Ok(catalogs)
+    }
+
+    /// Load the messages of a locale from a flat JSON object file
+    #[cfg(feature = "json")]
+    pub fn load_file<P: AsRef<Path>>(&mut self, locale: &str, path: P) -> Result<&mut Self, Error> {
+        let content = fs::read_to_string(&path)?;
+        let messages: HashMap<String, String> = serde_json::from_str(&content)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid catalog {}: {}", path.as_ref().display(), e)))?;
+        Ok(self.insert(locale, messages))
+    }
+
+    /// Add messages to a locale, replacing the ones with the same key
+    pub fn insert(&mut self, locale: &str, messages: HashMap<String, String>) -> &mut Self {
+        self.catalogs.entry(locale.to_string()).or_default().extend(messages);
+        self
+    }
+
+    /// Name of the available locale matching the tag, ignoring case
+    fn find(&self, tag: &str) -> Option<&str> {
+        self.catalogs.keys()
+            .find(|locale| locale.eq_ignore_ascii_case(tag))
+            .map(String::as_str)
+    }
+
+    pub fn locales(&self) -> Vec<&str> {
+        self.catalogs.keys().map(String::as_str).collect()
+    }
+
+    pub fn get(&self, locale: &str, key: &str) -> Option<&str> {
+        self.catalogs.get(locale)?.get(key).map(String::as_str)
+    }
+}
+
+/// The messages of the locale resolved for a request, put by the `I18n` layer
+/// in the extensions of the request and of the response.
+///
+/// Missing messages are looked up in the fallback locales: the language without
+/// its region (`pt` for `pt-BR`) and then the default locale.
+///
+/// # Example
+///
+///
+/// app.get("/", |req, res| {
+///     let t = req.extensions().get::<Translator>().unwrap();
+///     res.send(&t.t("greeting"))
+/// });
+///
+#[derive(Debug, Clone)]
+pub struct Translator {
+    locales: Vec<String>,
+    catalogs: Arc<Catalogs>,
+}
+
+impl Translator {
+    /// Resolved locale of the request
+    pub fn locale(&self) -> &str {
+        &self.locales[0]
+    }
+
+    /// Message of the key, in the first locale that has it
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.locales.iter().find_map(|locale| self.catalogs.get(locale, key))
+    }
+
+    /// Message of the key, or the key itself when no locale has it
+    pub fn t(&self, key: &str) -> String {
+        self.get(key).unwrap_or(key).to_string()
+    }
+}
+
+/// Resolves the locale of each request among the locales of its catalogs and makes
+/// the messages available to the handlers (as a `Translator` extension) and to the
+/// templates, which use `{{t "key"}}` for messages and `{{locale}}` for the locale.
+///
+/// The locale comes from the first of these that names an available locale:
+/// the query parameter and the cookie, when enabled, the `Accept-Language` header
+/// (a language also matches its regional variants and the other way around),
+/// and finally the default locale. The response gets `Content-Language`, and `Vary`
+/// lists `Accept-Language` (and `Cookie` when the cookie is enabled) next to the values
+/// set by other layers.
+///
+/// # Example
+///
+///
+/// app.layer(I18n::new(Catalogs::load_dir("locales")?, "en")
+///     .query_param("lang")
+///     .cookie("lang"));
+///
+/// // <html lang="{{locale}}"><h1>{{t "greeting"}}</h1>
+///
+pub struct I18n {
+    catalogs: Arc<Catalogs>,
+    default_locale: String,
+    query_param: Option<String>,
+    cookie: Option<String>,
+}
+
+impl I18n {
+    pub fn new(catalogs: Catalogs, default_locale: &str) -> Self {
+        I18n {
+            catalogs: Arc::new(catalogs),
+            default_locale: default_locale.to_string(),
+            query_param: None,
+            cookie: None,
+        }
+    }
+
+    /// Let the query parameter choose the locale, e.g. `?lang=es`
+    pub fn query_param(mut self, name: &str) -> Self {
+        self.query_param = Some(name.to_string());
+        self
+    }
+
+    /// Let the cookie choose the locale
+    pub fn cookie(mut self, name: &str) -> Self {
+        self.cookie = Some(name.to_string());
+        self
+    }
+
+    fn resolve(&self, req: &Request) -> String {
+        let from_query = self.query_param.as_ref().and_then(|name| {
+            let query = req.get_uri().split_once('?')?.1;
+            parsers::parse_urlencoded(query).into_iter().find(|(key, _)| key == name).map(|(_, value)| value)
+        });
+        let from_cookie = self.cookie.as_ref().and_then(|name| req.cookie(name));
+        let chosen = from_query.into_iter().chain(from_cookie)
+            .find_map(|tag| self.catalogs.find(&tag));
+        if let Some(locale) = chosen {
+            return locale.to_string();
+        }
+        for tag in req.accept_languages() {
+            let language = tag.split('-').next().unwrap_or_default();
+            let matched = self.catalogs.find(&tag)
+                .or_else(|| self.catalogs.find(language))
+                .or_else(|| {
+                    let mut variants: Vec<&str> = self.catalogs.locales().into_iter()
+                        .filter(|locale| locale.split('-').next().unwrap_or_default().eq_ignore_ascii_case(language))
+                        .collect();
+                    variants.sort();
+                    variants.first().copied()
+                });
+            if let Some(locale) = matched {
+                return locale.to_string();
+            }
+        }
+        self.default_locale.clone()
+    }
+
+    // The locale followed by its fallbacks
+    fn fallbacks(&self, locale: String) -> Vec<String> {
+        let mut locales = vec![locale];
+        if let Some((language, _)) = locales[0].split_once('-') {
+            if let Some(language) = self.catalogs.find(language) {
+                locales.push(language.to_string());
+            }
+        }
+        if !locales.contains(&self.default_locale) {
+            locales.push(self.default_locale.clone());
+        }
+        locales
+    }
+}
+
+impl Middleware for I18n {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next) {
+        let translator = Translator {
+            locales: self.fallbacks(self.resolve(req)),
+            catalogs: self.catalogs.clone(),
+        };
+        res.set_header("Content-Language", translator.locale());
+        res.append_header("Vary", "Accept-Language");
+        if self.cookie.is_some() {
+            res.append_header("Vary", "Cookie");
+        }
+        res.set_template_var("locale", translator.locale());
+        req.extensions_mut().insert(translator.clone());
+        res.extensions_mut().insert(translator);
+        next.run(req, res);
+    }
+}
+
+#[cfg(test)]
+mod test_i18n {
+    use std::collections::HashMap;
+
+    use super::{Catalogs, I18n, Translator};
+    use crate::http::{request::Request, response::Response};
+    use crate::middleware::{Middleware, Next};
+
+    fn catalogs() -> Catalogs {
+        let messages = |pairs: &[(&str, &str)]| -> HashMap<String, String> {
+            pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+        };
+        let mut catalogs = Catalogs::new();
+        catalogs.insert("en", messages(&[("greeting", "Hello"), ("farewell", "Bye")]))
+            .insert("pt", messages(&[("greeting", "Olá"), ("farewell", "Tchau")]))
+            .insert("pt-BR", messages(&[("greeting", "Oi")]))
+            .insert("es", messages(&[("greeting", "Hola")]));
+        catalogs
+    }
+
+    // Locale and messages resolved for the request
+    fn resolve(i18n: &I18n, head: &str) -> (String, String, String) {
+        let mut req = Request::new(&format!("{}\r\n\r\n", head)).unwrap();
+        let mut res = Response::empty();
+        i18n.handle(&mut req, &mut res, Next::new(&[], &|_req, _res| {}));
+        assert!(res.extensions().contains::<Translator>());
+        let t = req.extensions().get::<Translator>().unwrap();
+        (t.locale().to_string(), t.t("greeting"), t.t("farewell"))
+    }
+
+    #[test]
+    fn locale_resolution() {
+        let i18n = I18n::new(catalogs(), "en").query_param("lang").cookie("lang");
+        let resolved = |head: &str| resolve(&i18n, head).0;
+
+        assert_eq!(resolved("GET / HTTP/1.1"), "en");
+        assert_eq!(resolved("GET / HTTP/1.1\r\nAccept-Language: fr, es;q=0.8, en;q=0.5"), "es");
+        assert_eq!(resolved("GET / HTTP/1.1\r\nAccept-Language: es-MX"), "es");
+        assert_eq!(resolved("GET / HTTP/1.1\r\nAccept-Language: pt-br"), "pt-BR");
+        assert_eq!(resolved("GET / HTTP/1.1\r\nAccept-Language: fr"), "en");
+        // The query parameter wins over the cookie, which wins over the header
+        assert_eq!(resolved("GET /?lang=pt HTTP/1.1\r\nCookie: lang=es\r\nAccept-Language: en"), "pt");
+        assert_eq!(resolved("GET /?lang=xx HTTP/1.1\r\nCookie: lang=es\r\nAccept-Language: en"), "es");
+    }
+
+    #[test]
+    fn vary_is_appended() {
+        let vary = |i18n: I18n| {
+            let mut req = Request::new("GET / HTTP/1.1\r\n\r\n").unwrap();
+            let mut res = Response::empty();
+            res.set_header("Vary", "Accept-Encoding");
+            i18n.handle(&mut req, &mut res, Next::new(&[], &|_req, _res| {}));
+            res.get_header_values("Vary").iter().map(|value| value.as_str()).collect::<Vec<_>>().join(", ")
+        };
+        assert_eq!(vary(I18n::new(catalogs(), "en")), "Accept-Encoding, Accept-Language");
+        assert_eq!(vary(I18n::new(catalogs(), "en").cookie("lang")), "Accept-Encoding, Accept-Language, Cookie");
+    }
+
+    #[test]
+    fn fallback_messages() {
+        let i18n = I18n::new(catalogs(), "en");
+        let messages = |language: &str| {
+            let (_, greeting, farewell) = resolve(&i18n, &format!("GET / HTTP/1.1\r\nAccept-Language: {}", language));
+            format!("{} {}", greeting, farewell)
+        };
+        assert_eq!(messages("pt-BR"), "Oi Tchau");
+        assert_eq!(messages("es"), "Hola Bye");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn translated_templates() {
+        let i18n = I18n::new(Catalogs::load_dir("tests/files/locales").unwrap(), "en");
+        let mut req = Request::new("GET / HTTP/1.1\r\nAccept-Language: es-ES, en;q=0.5\r\n\r\n").unwrap();
+        let mut res = Response::empty();
+        let endpoint = |_req: &mut Request, res: &mut Response| {
+            res.render_template("i18n.html", HashMap::from([("name".to_string(), "Ana".to_string())]));
+        };
+        i18n.handle(&mut req, &mut res, Next::new(&[], &endpoint));
+        assert_eq!(res.get_header("Content-Language").unwrap(), "es");
+        assert_eq!(String::from_utf8_lossy(res.get_body().unwrap()), "<html lang=\"es\"><h1>Hola, Ana</h1><p>Bye missing</p></html>\n");
+    }
+}
@@ -0,0 +1,119 @@
+// Access control by client IP address
+
+use std::io::Error;
+use std::net::IpAddr;
+
+use crate::http::{request::Request, response::Response, status::HttpStatus};
+use crate::middleware::{Middleware, Next};
+use crate::utils::cidr::{self, Cidr};
+
+/// Allows or rejects requests by client IP address, matched against
+/// IPv4 and IPv6 ranges in CIDR notation.
+///
+/// A request is rejected with `403 Forbidden` when its address is in a denied range,
+/// or when there are allowed ranges and its address is in none of them.
+///
+/// The client address is `req.remote_addr()`, the peer address of the connection
+/// unless it is one of the proxies set with `App::trusted_proxies`, in which case it
+/// is the address they forwarded.
+///
+/// # Example
+///
+///
+/// app.trusted_proxies(&["10.0.0.2"], ProxyHeader::XForwarded)?;
+/// let admin = app.router("admin");
+/// admin.layer(IpFilter::new()
+///     .allow(&["10.0.0.0/8", "192.168.0.0/16", "fd00::/8", "127.0.0.1"])?
+///     .deny(&["10.66.0.0/16"])?);
+///
+#[derive(Default)]
+pub struct IpFilter {
+    allowed: Vec<Cidr>,
+    denied: Vec<Cidr>,
+}
+
+impl IpFilter {
+    pub fn new() -> Self {
+        IpFilter { allowed: Vec::new(), denied: Vec::new() }
+    }
+
+    /// Only accept clients in these ranges
+    pub fn allow(mut self, ranges: &[&str]) -> Result<Self, Error> {
+        self.allowed.extend(cidr::parse_list(ranges)?);
+        Ok(self)
+    }
+
+    /// Reject clients in these ranges, even if they are allowed
+    pub fn deny(mut self, ranges: &[&str]) -> Result<Self, Error> {
+        self.denied.extend(cidr::parse_list(ranges)?);
+        Ok(self)
+    }
+
+    fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.denied.iter().any(|range| range.contains(ip)) {
+            return false;
+        }
+        self.allowed.is_empty() || self.allowed.iter().any(|range| range.contains(ip))
+    }
+}
+
+impl Middleware for IpFilter {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next<'_>) {
+        match req.remote_addr().map(|addr| addr.ip()) {
+            Some(ip) if self.is_allowed(ip) => next.run(req, res),
+            ip => {
+                println!("Rejected request from {:?} on {}", ip, req.get_uri());
+                res.error(HttpStatus::Forbidden);
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_ip_filter {
+    use super::IpFilter;
+    use crate::http::forwarded::ProxyHeader;
+    use crate::http::{request::Request, response::Response, status::HttpStatus};
+    use crate::middleware::{Middleware, Next};
+    use crate::server::app::App;
+
+    fn status(layer: &IpFilter, peer: &str) -> HttpStatus {
+        let mut req = Request::new("GET /admin HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        req.set_remote_addr(peer.parse().unwrap());
+        let mut res = Response::empty();
+        let endpoint = |_req: &mut Request, res: &mut Response| res.set_status(HttpStatus::Ok);
+        layer.handle(&mut req, &mut res, Next::new(&[], &endpoint));
+        *res.get_status()
+    }
+
+    #[test]
+    fn allow_and_deny() {
+        let layer = IpFilter::new()
+            .allow(&["10.0.0.0/8", "fd00::/8"]).unwrap()
+            .deny(&["10.66.0.0/16"]).unwrap();
+        assert_eq!(status(&layer, "10.1.2.3:5000"), HttpStatus::Ok);
+        assert_eq!(status(&layer, "[fd00::5]:5000"), HttpStatus::Ok);
+        assert_eq!(status(&layer, "10.66.1.1:5000"), HttpStatus::Forbidden);
+        assert_eq!(status(&layer, "8.8.8.8:5000"), HttpStatus::Forbidden);
+        assert!(IpFilter::new().allow(&["10.0.0.0/40"]).is_err());
+    }
+
+    #[test]
+    fn address_resolved_by_the_app() {
+        let mut app = App::new("127.0.0.1", 4221);
+        app.trusted_proxies(&["10.0.0.0/24"], ProxyHeader::XForwarded).unwrap();
+        app.layer(IpFilter::new().allow(&["192.168.0.0/16"]).unwrap());
+        app.get("/admin", |_req, res| res.send("Dashboard"));
+        let status = |peer: &str, chain: &str| {
+            let mut req = Request::new(&format!("GET /admin HTTP/1.1\r\nX-Forwarded-For: {}\r\n\r\n", chain)).unwrap();
+            req.set_peer_addr(peer.parse().unwrap());
+            *app.process(&mut req).get_status()
+        };
+        assert_eq!(status("10.0.0.2:5000", "192.168.1.9, 10.0.0.3"), HttpStatus::Ok);
+        // A client can't prepend a spoofed address
+        assert_eq!(status("10.0.0.2:5000", "192.168.1.9, 8.8.8.8"), HttpStatus::Forbidden);
+        // Forwarded headers of untrusted peers are ignored
+        assert_eq!(status("8.8.8.8:5000", "192.168.1.9"), HttpStatus::Forbidden);
+    }
+}
@@ -0,0 +1,337 @@
+// JWT verification built on top of the Bearer auth guard
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use jsonwebtoken::errors::ErrorKind as JwtErrorKind;
+use jsonwebtoken::jwk::{AlgorithmParameters, Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+
+use crate::http::{request::Request, response::Response};
+use crate::middleware::auth::{credentials, unauthorized, Principal};
+use crate::middleware::{Middleware, Next};
+
+// Algorithms accepted for tokens, anything else (including `none`) is rejected
+const RSA_ALGORITHMS: [Algorithm; 3] = [Algorithm::RS256, Algorithm::RS384, Algorithm::RS512];
+const HMAC_ALGORITHMS: [Algorithm; 3] = [Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+
+struct Key {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    key: DecodingKey,
+}
+
+/// Set of keys used to verify token signatures.
+///
+/// Keys loaded from a JWK carry their `kid` and are only used for tokens with the
+/// same `kid`. Secrets and PEM keys are tried for every token of their algorithm.
+///
+/// # Example
+///
+///
+/// let keys = KeySet::new()
+///     .hs256(b"shared-secret")
+///     .rs256_pem_file("keys/idp.pem")?
+///     .jwk_file("keys/jwks.json")?;
+///
+#[derive(Default)]
+pub struct KeySet {
+    keys: Vec<Key>,
+}
+
+fn invalid_key<E: std::fmt::Display>(err: E) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Invalid key: {}", err))
+}
+
+impl KeySet {
+    pub fn new() -> Self {
+        KeySet { keys: Vec::new() }
+    }
+
+    /// Add a shared secret for HS256 tokens
+    pub fn hs256(mut self, secret: &[u8]) -> Self {
+        self.keys.push(Key { kid: None, algorithm: Algorithm::HS256, key: DecodingKey::from_secret(secret) });
+        self
+    }
+
+    /// Add a PEM encoded RSA public key for RS256 tokens
+    pub fn rs256_pem(mut self, pem: &[u8]) -> Result<Self, Error> {
+        let key = DecodingKey::from_rsa_pem(pem).map_err(invalid_key)?;
+        self.keys.push(Key { kid: None, algorithm: Algorithm::RS256, key });
+        Ok(self)
+    }
+
+    /// Read a PEM encoded RSA public key from a file
+    pub fn rs256_pem_file(self, path: &str) -> Result<Self, Error> {
+        let pem = fs::read(path)?;
+        self.rs256_pem(&pem)
+    }
+
+    /// Add the keys of a JWK (`{"kty": ...}`) or a JWK set (`{"keys": [...]}`).
+    /// Only RSA and symmetric keys are supported. A key is only used with the algorithm
+    /// of its `alg` (RS256/384/512 or HS256/384/512), RS256 or HS256 when it has none.
+    pub fn jwk(mut self, json: &str) -> Result<Self, Error> {
+        let jwks: Vec<Jwk> = match serde_json::from_str::<JwkSet>(json) {
+            Ok(set) => set.keys,
+            Err(_) => vec![serde_json::from_str::<Jwk>(json).map_err(invalid_key)?],
+        };
+
+        for jwk in jwks {
+            let allowed: &[Algorithm] = match jwk.algorithm {
+                AlgorithmParameters::RSA(_) => &RSA_ALGORITHMS,
+                AlgorithmParameters::OctetKey(_) => &HMAC_ALGORITHMS,
+                _ => return Err(invalid_key("only RSA and oct keys are supported")),
+            };
+            let algorithm = match jwk.common.key_algorithm {
+                None => allowed[0],
+                // Both enums name the algorithms the same way
+                Some(alg) => allowed.iter().copied()
+                    .find(|algorithm| format!("{:?}", algorithm) == format!("{:?}", alg))
+                    .ok_or_else(|| invalid_key(format!("algorithm {:?} is not allowed for this key", alg)))?,
+            };
+            let key = DecodingKey::from_jwk(&jwk).map_err(invalid_key)?;
+            self.keys.push(Key { kid: jwk.common.key_id.clone(), algorithm, key });
+        }
+        Ok(self)
+    }
+
+    /// Read a JWK or a JWK set from a file
+    pub fn jwk_file(self, path: &str) -> Result<Self, Error> {
+        let json = fs::read_to_string(path)?;
+        self.jwk(&json)
+    }
+
+    fn candidates<'a>(&'a self, algorithm: Algorithm, kid: Option<&'a str>) -> impl Iterator<Item = &'a Key> {
+        self.keys.iter().filter(move |key| {
+            key.algorithm == algorithm && match (&key.kid, kid) {
+                (Some(expected), Some(kid)) => expected == kid,
+                (Some(_), None) => false,
+                (None, _) => true,
+            }
+        })
+    }
+}
+
+/// JWT Bearer authentication guard.
+///
+/// Verifies the signature of the token with the configured `KeySet` (HS256/384/512 and
+/// RS256/384/512), validates `exp` and `nbf` with the given leeway and, when configured,
+/// `aud` and `iss`. Tokens must have a `sub` claim, which becomes the principal of the
+/// request. The claims can be read with `req.claims::<T>()`.
+///
+/// # Example
+///
+///
+/// let keys = KeySet::new().rs256_pem_file("keys/idp.pem")?;
+/// app.layer(JwtAuth::new("api", keys)
+///     .issuer("https://idp.example.com")
+///     .audience("orders")
+///     .leeway(Duration::from_secs(30)));
+///
+pub struct JwtAuth {
+    realm: String,
+    keys: KeySet,
+    leeway: Duration,
+    audience: Vec<String>,
+    issuer: Vec<String>,
+}
+
+impl JwtAuth {
+    pub fn new(realm: &str, keys: KeySet) -> Self {
+        JwtAuth {
+            realm: realm.to_string(),
+            keys,
+            leeway: Duration::from_secs(60),
+            audience: Vec::new(),
+            issuer: Vec::new(),
+        }
+    }
+
+    /// Accept tokens issued for this audience. Can be called several times.
+    pub fn audience(mut self, audience: &str) -> Self {
+        self.audience.push(audience.to_string());
+        self
+    }
+
+    /// Accept tokens issued by this issuer. Can be called several times.
+    pub fn issuer(mut self, issuer: &str) -> Self {
+        self.issuer.push(issuer.to_string());
+        self
+    }
+
+    /// Clock skew tolerated when checking `exp` and `nbf` (60 seconds by default)
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    fn validation(&self, algorithm: Algorithm) -> Validation {
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = self.leeway.as_secs();
+        validation.validate_nbf = true;
+        validation.validate_aud = !self.audience.is_empty();
+        if !self.audience.is_empty() {
+            validation.set_audience(&self.audience);
+        }
+        if !self.issuer.is_empty() {
+            validation.set_issuer(&self.issuer);
+        }
+        validation
+    }
+
+    fn verify(&self, token: &str) -> Result<Value, &'static str> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| "Malformed token")?;
+        if !RSA_ALGORITHMS.contains(&header.alg) && !HMAC_ALGORITHMS.contains(&header.alg) {
+            return Err("Unsupported algorithm");
+        }
+
+        let validation = self.validation(header.alg);
+        let mut error = "Unknown signing key";
+        for key in self.keys.candidates(header.alg, header.kid.as_deref()) {
+            match jsonwebtoken::decode::<Value>(token, &key.key, &validation) {
+                Ok(data) => return match data.claims.get("sub").and_then(Value::as_str) {
+                    Some(subject) if !subject.is_empty() => Ok(data.claims),
+                    _ => Err("Missing subject"),
+                },
+                Err(err) => {
+                    error = match err.kind() {
+                        // Try the next key, the token may be signed with it
+                        JwtErrorKind::InvalidSignature => "Invalid signature",
+                        JwtErrorKind::ExpiredSignature => return Err("Token expired"),
+                        JwtErrorKind::ImmatureSignature => return Err("Token not yet valid"),
+                        JwtErrorKind::InvalidAudience => return Err("Invalid audience"),
+                        JwtErrorKind::InvalidIssuer => return Err("Invalid issuer"),
+                        JwtErrorKind::MissingRequiredClaim(_) => return Err("Missing required claim"),
+                        _ => return Err("Malformed token"),
+                    }
+                }
+            }
+        }
+        Err(error)
+    }
+}
+
+impl Middleware for JwtAuth {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next<'_>) {
+        let token = match credentials(req, "Bearer") {
+            Some(token) if !token.is_empty() => token.to_string(),
+            _ => return unauthorized(res, &format!("Bearer realm=\"{}\"", self.realm)),
+        };
+
+        match self.verify(&token) {
+            Ok(claims) => {
+                // Checked by verify
+                let subject = claims.get("sub").and_then(Value::as_str).unwrap_or_default();
+                req.set_principal(Principal::new(subject, "Bearer"));
+                req.set_claims(claims);
+                next.run(req, res);
+            },
+            Err(description) => {
+                println!("Rejected JWT on {}: {}", req.get_uri(), description);
+                let challenge = format!("Bearer realm=\"{}\", error=\"invalid_token\", error_description=\"{}\"", self.realm, description);
+                unauthorized(res, &challenge);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_jwt {
+    use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::{JwtAuth, KeySet};
+
+    #[derive(Deserialize)]
+    struct Claims {
+        sub: String,
+        role: String,
+    }
+
+    fn hs256(claims: serde_json::Value, secret: &[u8], kid: Option<&str>) -> String {
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = kid.map(str::to_string);
+        encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    #[test]
+    fn hs256_claims() {
+        let auth = JwtAuth::new("api", KeySet::new().hs256(b"secret"))
+            .audience("orders")
+            .issuer("idp");
+        let exp = get_current_timestamp() + 60;
+
+        let token = hs256(json!({"sub": "ana", "role": "admin", "exp": exp, "aud": "orders", "iss": "idp"}), b"secret", None);
+        let claims: Claims = serde_json::from_value(auth.verify(&token).unwrap()).unwrap();
+        assert_eq!((claims.sub.as_str(), claims.role.as_str()), ("ana", "admin"));
+
+        let token = hs256(json!({"sub": "ana", "exp": exp, "aud": "orders", "iss": "idp"}), b"other", None);
+        assert_eq!(auth.verify(&token), Err("Invalid signature"));
+
+        let token = hs256(json!({"sub": "ana", "exp": exp, "aud": "billing", "iss": "idp"}), b"secret", None);
+        assert_eq!(auth.verify(&token), Err("Invalid audience"));
+
+        let token = hs256(json!({"role": "admin", "exp": exp, "aud": "orders", "iss": "idp"}), b"secret", None);
+        assert_eq!(auth.verify(&token), Err("Missing subject"));
+    }
+
+    #[test]
+    fn time_claims_with_leeway() {
+        let auth = JwtAuth::new("api", KeySet::new().hs256(b"secret")).leeway(std::time::Duration::from_secs(30));
+        let now = get_current_timestamp();
+
+        let token = hs256(json!({"sub": "ana", "exp": now - 10}), b"secret", None);
+        assert!(auth.verify(&token).is_ok());
+
+        let token = hs256(json!({"sub": "ana", "exp": now - 120}), b"secret", None);
+        assert_eq!(auth.verify(&token), Err("Token expired"));
+
+        let token = hs256(json!({"sub": "ana", "exp": now + 600, "nbf": now + 120}), b"secret", None);
+        assert_eq!(auth.verify(&token), Err("Token not yet valid"));
+    }
+
+    #[test]
+    fn key_files() {
+        let keys = KeySet::new()
+            .rs256_pem_file("tests/keys/rs256_public.pem").unwrap()
+            .jwk_file("tests/keys/jwks.json").unwrap();
+        let auth = JwtAuth::new("api", keys);
+        let claims = json!({"sub": "ana", "exp": get_current_timestamp() + 60});
+
+        let private = std::fs::read("tests/keys/rs256_private.pem").unwrap();
+        let token = encode(&Header::new(Algorithm::RS256), &claims, &EncodingKey::from_rsa_pem(&private).unwrap()).unwrap();
+        assert!(auth.verify(&token).is_ok());
+
+        let token = hs256(claims.clone(), b"jwk-test-secret", Some("hs-1"));
+        assert!(auth.verify(&token).is_ok());
+
+        let token = hs256(claims, b"jwk-test-secret", Some("hs-2"));
+        assert_eq!(auth.verify(&token), Err("Unknown signing key"));
+    }
+
+    #[test]
+    fn jwk_algorithm() {
+        let keys = KeySet::new().jwk(r#"{"kty": "oct", "kid": "hs-512", "alg": "HS512", "k": "c2VjcmV0"}"#).unwrap();
+        let auth = JwtAuth::new("api", keys);
+        let claims = json!({"sub": "ana", "exp": get_current_timestamp() + 60});
+
+        let mut header = Header::new(Algorithm::HS512);
+        header.kid = Some("hs-512".to_string());
+        let token = encode(&header, &claims, &EncodingKey::from_secret(b"secret")).unwrap();
+        assert!(auth.verify(&token).is_ok());
+
+        // The key is not used with another algorithm than its own
+        let token = hs256(claims, b"secret", Some("hs-512"));
+        assert_eq!(auth.verify(&token), Err("Unknown signing key"));
+
+        // Algorithms of another family are refused
+        let jwk = r#"{"kty": "oct", "alg": "RS256", "k": "c2VjcmV0"}"#;
+        assert!(KeySet::new().jwk(jwk).is_err());
+        let jwk = r#"{"kty": "oct", "alg": "ES256", "k": "c2VjcmV0"}"#;
+        assert!(KeySet::new().jwk(jwk).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+// Middleware layers that wrap the execution of a route
+
+use std::sync::Arc;
+
+use crate::http::{request::Request, response::Response};
+
+pub(crate) mod rate_limit;
+pub(crate) mod auth;
+#[cfg(feature = "json")]
+pub(crate) mod jwt;
+#[cfg(feature = "json")]
+pub(crate) mod session;
+#[cfg(feature = "json")]
+pub(crate) mod session_store;
+pub(crate) mod csrf;
+pub(crate) mod security_headers;
+pub(crate) mod timeout;
+pub(crate) mod ip_filter;
+pub(crate) mod i18n;
+
+/// A layer that runs around a route action.
+///
+/// The layer receives the request, the response being built and a `Next` handle.
+/// Calling `next.run(req, res)` continues with the following layer (or the route
+/// action itself); returning without calling it short-circuits the request.
+///
+/// # Example
+///
+///
+/// struct Logger;
+///
+/// impl Middleware for Logger {
+///     fn handle(&self, req: &mut Request, res: &mut Response, next: Next) {
+///         println!("--> {}", req.get_uri());
+///         next.run(req, res);
+///         println!("<-- {}", res.get_status());
+///     }
+/// }
+///
+pub trait Middleware: Send + Sync {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next<'_>);
+}
+
+pub(crate) type Layers = Vec<Arc<dyn Middleware>>;
+
+/// Handle to the rest of the middleware chain
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    endpoint: &'a dyn Fn(&mut Request, &mut Response),
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(layers: &'a [Arc<dyn Middleware>], endpoint: &'a dyn Fn(&mut Request, &mut Response)) -> Self {
+        Next { layers, endpoint }
+    }
+
+    /// Run the remaining layers and finally the endpoint
+    pub fn run(self, req: &mut Request, res: &mut Response) {
+        match self.layers.split_first() {
+            Some((layer, rest)) => layer.handle(req, res, Next::new(rest, self.endpoint)),
+            None => (self.endpoint)(req, res),
+        }
+    }
+}
@@ -0,0 +1,247 @@
+// Token bucket rate limiting keyed by client
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::http::{request::Request, response::Response, status::HttpStatus};
+use crate::middleware::{Middleware, Next};
+
+// Most clients tracked at once, the least recently seen ones are evicted past it
+const MAX_BUCKETS: usize = 10_000;
+
+type KeyFn = Arc<dyn Fn(&Request) -> Option<String> + Send + Sync>;
+
+enum RateLimitKey {
+    RemoteIp,
+    Header(String),
+    Custom(KeyFn),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Outcome of taking a token from a bucket
+enum Decision {
+    Allowed { remaining: u32, reset: Duration },
+    Limited { retry_after: Duration, reset: Duration },
+}
+
+/// Rate limiting layer backed by in-memory token buckets.
+///
+/// Every client gets a bucket holding `limit` tokens which refills continuously
+/// over `window`. Each request takes one token; when the bucket is empty the
+/// request is answered with `429 Too Many Requests` and a `Retry-After` header.
+/// Every response carries the `RateLimit-Limit`, `RateLimit-Remaining` and
+/// `RateLimit-Reset` headers.
+///
+/// Clients are keyed by remote IP by default, see `by_header` and `by_key`.
+///
+/// # Example
+///
+///
+/// // 100 requests per minute for each IP
+/// app.layer(RateLimit::new(100, Duration::from_secs(60)));
+///
+/// // 10 requests per second for each API key on a single route
+/// app.get("/search", handler)
+///     .layer(RateLimit::new(10, Duration::from_secs(1)).by_header("X-Api-Key"));
+///
+pub struct RateLimit {
+    limit: u32,
+    window: Duration,
+    key: RateLimitKey,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    max_buckets: usize,
+}
+
+impl RateLimit {
+
+    /// Allow `limit` requests per `window` for each client IP
+    pub fn new(limit: u32, window: Duration) -> Self {
+        assert!(limit > 0, "Rate limit must allow at least one request");
+        assert!(!window.is_zero(), "Rate limit window must not be empty");
+        RateLimit {
+            limit,
+            window,
+            key: RateLimitKey::RemoteIp,
+            buckets: Mutex::new(HashMap::new()),
+            max_buckets: MAX_BUCKETS,
+        }
+    }
+
+    /// Key clients by the value of a header (e.g. an API key).
+    /// Requests without the header are keyed by remote IP.
+    pub fn by_header(mut self, name: &str) -> Self {
+        self.key = RateLimitKey::Header(name.to_string());
+        self
+    }
+
+    /// Key clients with a custom function.
+    /// Requests for which the function returns `None` are not limited.
+    pub fn by_key<F>(mut self, key: F) -> Self
+        where F: Fn(&Request) -> Option<String> + Send + Sync + 'static
+    {
+        self.key = RateLimitKey::Custom(Arc::new(key));
+        self
+    }
+
+    fn client_key(&self, req: &Request) -> Option<String> {
+        let remote_ip = || match req.remote_addr() {
+            Some(addr) => format!("ip:{}", addr.ip()),
+            None => "ip:unknown".to_string(),
+        };
+        match &self.key {
+            RateLimitKey::RemoteIp => Some(remote_ip()),
+            RateLimitKey::Header(name) => match req.get_header_field(name) {
+                Some(value) => Some(format!("header:{}", value)),
+                None => Some(remote_ip()),
+            },
+            RateLimitKey::Custom(key) => key(req).map(|key| format!("custom:{}", key)),
+        }
+    }
+
+    // Tokens regained per second
+    fn rate(&self) -> f64 {
+        self.limit as f64 / self.window.as_secs_f64()
+    }
+
+    fn acquire(&self, key: &str, now: Instant) -> Decision {
+        let rate = self.rate();
+        let capacity = self.limit as f64;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() >= self.max_buckets && !buckets.contains_key(key) {
+            self.evict(&mut buckets, now);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Decision::Allowed {
+                remaining: bucket.tokens.floor() as u32,
+                reset: Duration::from_secs_f64((capacity - bucket.tokens) / rate),
+            }
+        } else {
+            Decision::Limited {
+                retry_after: Duration::from_secs_f64((1.0 - bucket.tokens) / rate),
+                reset: Duration::from_secs_f64((capacity - bucket.tokens) / rate),
+            }
+        }
+    }
+
+    // Make room for a new client. Buckets that would already be full again are dropped,
+    // then the least recently used tenth of the map, so a flood of new keys can't grow it.
+    fn evict(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        let window = self.window;
+        buckets.retain(|_, bucket| now.duration_since(bucket.updated) < window);
+        if buckets.len() < self.max_buckets {
+            return;
+        }
+        let mut updated: Vec<Instant> = buckets.values().map(|bucket| bucket.updated).collect();
+        let evicted = (buckets.len() / 10).max(buckets.len() + 1 - self.max_buckets);
+        let (_, oldest_kept, _) = updated.select_nth_unstable(evicted - 1);
+        let cutoff = *oldest_kept;
+        buckets.retain(|_, bucket| bucket.updated > cutoff);
+    }
+}
+
+// Round up to whole seconds, as required by Retry-After and RateLimit-Reset
+fn seconds(duration: Duration) -> String {
+    duration.as_secs_f64().ceil().to_string()
+}
+
+impl Middleware for RateLimit {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next<'_>) {
+        let key = match self.client_key(req) {
+            Some(key) => key,
+            None => return next.run(req, res),
+        };
+
+        match self.acquire(&key, Instant::now()) {
+            Decision::Allowed { remaining, reset } => {
+                next.run(req, res);
+                res.set_header("RateLimit-Limit", &self.limit.to_string());
+                res.set_header("RateLimit-Remaining", &remaining.to_string());
+                res.set_header("RateLimit-Reset", &seconds(reset));
+            },
+            Decision::Limited { retry_after, reset } => {
+                println!("Rate limit exceeded for {} on {}", key, req.get_uri());
+                res.error(HttpStatus::TooManyRequests);
+                res.set_header("Retry-After", &seconds(retry_after));
+                res.set_header("RateLimit-Limit", &self.limit.to_string());
+                res.set_header("RateLimit-Remaining", "0");
+                res.set_header("RateLimit-Reset", &seconds(reset));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_rate_limit {
+    use std::time::{Duration, Instant};
+
+    use super::{Decision, RateLimit};
+    use crate::http::request::Request;
+
+    #[test]
+    fn bucket_empties_and_refills() {
+        let limit = RateLimit::new(2, Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert!(matches!(limit.acquire("a", start), Decision::Allowed { remaining: 1, .. }));
+        assert!(matches!(limit.acquire("a", start), Decision::Allowed { remaining: 0, .. }));
+        match limit.acquire("a", start) {
+            Decision::Limited { retry_after, .. } => assert_eq!(retry_after.as_secs(), 5),
+            Decision::Allowed { .. } => panic!("third request should be limited"),
+        }
+
+        // Other clients have their own bucket
+        assert!(matches!(limit.acquire("b", start), Decision::Allowed { .. }));
+
+        // One token is back after window / limit
+        assert!(matches!(limit.acquire("a", start + Duration::from_secs(5)), Decision::Allowed { .. }));
+    }
+
+    #[test]
+    fn least_recently_used_are_evicted() {
+        let mut limit = RateLimit::new(1, Duration::from_secs(60));
+        limit.max_buckets = 20;
+        let start = Instant::now();
+
+        assert!(matches!(limit.acquire("steady", start), Decision::Allowed { .. }));
+        // A flood of new clients, "steady" keeps coming back in between
+        for i in 0..100u64 {
+            let now = start + Duration::from_millis(i + 1);
+            limit.acquire(&format!("flood-{}", i), now);
+            if i % 10 == 0 {
+                assert!(matches!(limit.acquire("steady", now), Decision::Limited { .. }));
+            }
+            assert!(limit.buckets.lock().unwrap().len() <= 20);
+        }
+        // The oldest keys were evicted, the recent ones are still limited
+        let buckets = limit.buckets.lock().unwrap();
+        assert!(!buckets.contains_key("flood-0"));
+        assert!(buckets.contains_key("flood-99"));
+        assert!(buckets.contains_key("steady"));
+    }
+
+    #[test]
+    fn header_key_falls_back_to_ip() {
+        let limit = RateLimit::new(1, Duration::from_secs(1)).by_header("X-Api-Key");
+
+        let mut req = Request::new("GET / HTTP/1.1\r\nx-api-key: secret\r\n\r\n").unwrap();
+        assert_eq!(limit.client_key(&req), Some("header:secret".to_string()));
+
+        req.set_headers(Default::default());
+        req.set_remote_addr("10.0.0.1:5000".parse().unwrap());
+        assert_eq!(limit.client_key(&req), Some("ip:10.0.0.1".to_string()));
+    }
+}
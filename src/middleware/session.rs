@@ -0,0 +1,309 @@
+// Server-side sessions identified by a cookie
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::http::cookie::{Cookie, SameSite};
+use crate::http::{request::Request, response::Response};
+use crate::middleware::session_store::{SessionData, SessionStore};
+use crate::middleware::{Middleware, Next};
+use crate::utils::random;
+
+// Expired sessions are purged from the store every this many requests
+const PURGE_INTERVAL: usize = 1000;
+
+struct SessionState {
+    id: Option<String>,
+    values: HashMap<String, Value>,
+    expires: SystemTime,
+    changed: bool,
+    regenerate: bool,
+    destroyed: bool,
+}
+
+/// Session of the current request, available with `req.session()`.
+///
+/// Handles are cheap to clone and share the same state, so the session can be
+/// modified from a handler that only has a `&Request`. Changes are saved in the
+/// store by the `Sessions` middleware once the handler returns.
+///
+/// # Example
+///
+///
+/// app.post("/login", |req, res| {
+///     let session = req.session().unwrap();
+///     // New id on privilege change, against session fixation
+///     session.regenerate();
+///     session.set("user_id", 42);
+///     res
+/// });
+///
+#[derive(Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+impl Session {
+    fn new(id: Option<String>, data: Option<SessionData>, ttl: Duration) -> Self {
+        let (values, expires) = match data {
+            Some(data) => (data.values, data.expires),
+            None => (HashMap::new(), SystemTime::now() + ttl),
+        };
+        Session {
+            state: Arc::new(Mutex::new(SessionState {
+                id,
+                values,
+                expires,
+                changed: false,
+                regenerate: false,
+                destroyed: false,
+            })),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Id of the session, `None` for a new session that has not been saved yet
+    pub fn id(&self) -> Option<String> {
+        self.state().id.clone()
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.state().values.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn set<T: Serialize>(&self, key: &str, value: T) {
+        let value = serde_json::to_value(value).expect("Session values must serialize to JSON");
+        let mut state = self.state();
+        state.values.insert(key.to_string(), value);
+        state.changed = true;
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut state = self.state();
+        if state.values.remove(key).is_some() {
+            state.changed = true;
+        }
+    }
+
+    /// Remove every value, keeping the session
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.values.clear();
+        state.changed = true;
+    }
+
+    /// Move the values to a new session id and drop the old one.
+    /// Call it whenever the privileges of the user change (login, logout, sudo...).
+    pub fn regenerate(&self) {
+        let mut state = self.state();
+        state.regenerate = true;
+        state.changed = true;
+    }
+
+    /// Delete the session from the store and the client
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.values.clear();
+        state.destroyed = true;
+    }
+}
+
+/// Session middleware.
+///
+/// Loads the session named by the session cookie from the store, exposes it to the
+/// handler with `req.session()` and saves it back when it changed. Sessions expire
+/// after `ttl` without changes or activity.
+///
+/// # Example
+///
+///
+/// app.layer(Sessions::new(MemoryStore::new()).ttl(Duration::from_secs(3600)));
+///
+/// // Or keep them on disk
+/// app.layer(Sessions::new(FileStore::new("sessions")?));
+///
+pub struct Sessions {
+    store: Box<dyn SessionStore>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    same_site: SameSite,
+    requests: AtomicUsize,
+}
+
+impl Sessions {
+    pub fn new<S: SessionStore + 'static>(store: S) -> Self {
+        Sessions {
+            store: Box::new(store),
+            cookie_name: "sid".to_string(),
+            ttl: Duration::from_secs(24 * 60 * 60),
+            secure: true,
+            same_site: SameSite::Lax,
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// Name of the session cookie ("sid" by default)
+    pub fn cookie_name(mut self, name: &str) -> Self {
+        self.cookie_name = name.to_string();
+        self
+    }
+
+    /// Lifetime of an idle session (one day by default)
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Send the cookie only over HTTPS (enabled by default)
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    fn cookie(&self, id: &str) -> Cookie {
+        Cookie::new(&self.cookie_name, id)
+            .path("/")
+            .max_age(self.ttl)
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+    }
+
+    fn load(&self, req: &Request) -> Session {
+        if let Some(id) = req.cookie(&self.cookie_name) {
+            if let Some(data) = self.store.load(&id) {
+                return Session::new(Some(id), Some(data), self.ttl);
+            }
+        }
+        Session::new(None, None, self.ttl)
+    }
+
+    fn save(&self, session: &Session, res: &mut Response) {
+        let mut state = session.state();
+
+        if state.destroyed {
+            if let Some(id) = state.id.take() {
+                if let Err(e) = self.store.remove(&id) {
+                    println!("Error removing session: {}", e);
+                }
+                res.set_cookie(Cookie::removal(&self.cookie_name));
+            }
+            return;
+        }
+
+        // Refresh sessions that are past half of their lifetime, even without changes
+        let now = SystemTime::now();
+        let stale = state.expires.duration_since(now).unwrap_or_default() < self.ttl / 2;
+        let refresh = stale && state.id.is_some();
+        if !(state.changed || refresh) {
+            return;
+        }
+        // Nothing worth a cookie yet
+        if state.id.is_none() && state.values.is_empty() {
+            return;
+        }
+
+        if state.regenerate {
+            if let Some(old) = state.id.take() {
+                if let Err(e) = self.store.remove(&old) {
+                    println!("Error removing session: {}", e);
+                }
+            }
+        }
+        let id = state.id.get_or_insert_with(|| random::token(32)).clone();
+
+        state.expires = now + self.ttl;
+        let data = SessionData { values: state.values.clone(), expires: state.expires };
+        match self.store.save(&id, &data) {
+            Ok(_) => {
+                res.set_cookie(self.cookie(&id));
+            },
+            Err(e) => println!("Error saving session: {}", e),
+        }
+    }
+}
+
+impl Middleware for Sessions {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next<'_>) {
+        if self.requests.fetch_add(1, Ordering::Relaxed) % PURGE_INTERVAL == PURGE_INTERVAL - 1 {
+            self.store.purge_expired();
+        }
+
+        let session = self.load(req);
+        req.set_session(session.clone());
+        next.run(req, res);
+        self.save(&session, res);
+    }
+}
+
+
+#[cfg(test)]
+mod test_session {
+    use std::sync::Arc;
+
+    use super::Sessions;
+    use crate::http::{request::Request, response::Response};
+    use crate::middleware::session_store::{MemoryStore, SessionStore};
+    use crate::middleware::{Middleware, Next};
+
+    // Run a request through the layer with the given session cookie
+    fn run(layer: &Sessions, sid: Option<&str>, action: &dyn Fn(&mut Request, &mut Response)) -> Response {
+        let mut req = Request::new("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        if let Some(sid) = sid {
+            req.set_header_field("Cookie", &format!("sid={}", sid));
+        }
+        let mut res = Response::empty();
+        layer.handle(&mut req, &mut res, Next::new(&[], action));
+        res
+    }
+
+    fn sid(res: &Response) -> String {
+        let cookie = res.get_header("Set-Cookie").unwrap();
+        cookie.split(';').next().unwrap().trim_start_matches("sid=").to_string()
+    }
+
+    #[test]
+    fn session_lifecycle() {
+        let store = Arc::new(MemoryStore::new());
+        let layer = Sessions::new(store.clone());
+
+        // Untouched sessions don't set a cookie
+        let res = run(&layer, None, &|_req, _res| {});
+        assert!(res.get_header("Set-Cookie").is_none());
+
+        let res = run(&layer, None, &|req, _res| req.session().unwrap().set("user", 42));
+        let first = sid(&res);
+        assert!(res.get_header("Set-Cookie").unwrap().contains("HttpOnly; SameSite=Lax"));
+
+        run(&layer, Some(&first), &|req, _res| {
+            assert_eq!(req.session().unwrap().get::<u32>("user"), Some(42));
+        });
+
+        // Regeneration moves the data to a new id
+        let res = run(&layer, Some(&first), &|req, _res| req.session().unwrap().regenerate());
+        let second = sid(&res);
+        assert_ne!(first, second);
+        assert!(store.load(&first).is_none());
+        assert_eq!(store.load(&second).unwrap().values["user"], 42);
+
+        let res = run(&layer, Some(&second), &|req, _res| req.session().unwrap().destroy());
+        assert!(res.get_header("Set-Cookie").unwrap().contains("Max-Age=0"));
+        assert!(store.load(&second).is_none());
+    }
+}
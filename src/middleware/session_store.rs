@@ -0,0 +1,237 @@
+// Storage backends for server-side sessions
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Map, Value};
+
+use crate::utils::random;
+
+/// Values of a session and the moment it expires
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionData {
+    pub values: HashMap<String, Value>,
+    pub expires: SystemTime,
+}
+
+impl SessionData {
+    pub fn is_expired(&self) -> bool {
+        self.expires <= SystemTime::now()
+    }
+
+    fn to_json(&self) -> Value {
+        let expires = self.expires.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        json!({ "expires": expires, "values": self.values })
+    }
+
+    fn from_json(json: &Value) -> Option<Self> {
+        let expires = UNIX_EPOCH + Duration::from_secs(json.get("expires")?.as_u64()?);
+        let values: &Map<String, Value> = json.get("values")?.as_object()?;
+        Some(SessionData {
+            values: values.clone().into_iter().collect(),
+            expires,
+        })
+    }
+}
+
+/// Backend where the session middleware keeps the sessions.
+/// `load` must not return sessions that already expired.
+pub trait SessionStore: Send + Sync {
+    fn load(&self, id: &str) -> Option<SessionData>;
+    fn save(&self, id: &str, data: &SessionData) -> Result<(), Error>;
+    fn remove(&self, id: &str) -> Result<(), Error>;
+
+    /// Delete every expired session
+    fn purge_expired(&self) {}
+}
+
+// Lets the application keep a handle to the store given to the middleware
+impl<S: SessionStore + ?Sized> SessionStore for Arc<S> {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        (**self).load(id)
+    }
+
+    fn save(&self, id: &str, data: &SessionData) -> Result<(), Error> {
+        (**self).save(id, data)
+    }
+
+    fn remove(&self, id: &str) -> Result<(), Error> {
+        (**self).remove(id)
+    }
+
+    fn purge_expired(&self) {
+        (**self).purge_expired()
+    }
+}
+
+/// Sessions kept in the memory of the process.
+/// They are lost when the server stops.
+#[derive(Default)]
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, SessionData>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore { sessions: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.get(id).filter(|data| !data.is_expired()).cloned()
+    }
+
+    fn save(&self, id: &str, data: &SessionData) -> Result<(), Error> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.insert(id.to_string(), data.clone());
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<(), Error> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.remove(id);
+        Ok(())
+    }
+
+    fn purge_expired(&self) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.retain(|_, data| !data.is_expired());
+    }
+}
+
+/// Sessions kept as JSON files (one per session) in a directory
+pub struct FileStore {
+    directory: PathBuf,
+}
+
+impl FileStore {
+    /// Create the store, creating the directory if needed
+    pub fn new(directory: &str) -> Result<Self, Error> {
+        fs::create_dir_all(directory)?;
+        Ok(FileStore { directory: PathBuf::from(directory) })
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf, Error> {
+        // Ids come from a cookie, never let them escape the directory
+        let valid = !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        match valid {
+            true => Ok(self.directory.join(format!("{}.json", id))),
+            false => Err(Error::new(ErrorKind::InvalidInput, "Invalid session id")),
+        }
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> Option<SessionData> {
+        let content = fs::read_to_string(self.path(id).ok()?).ok()?;
+        let data = SessionData::from_json(&serde_json::from_str(&content).ok()?)?;
+        match data.is_expired() {
+            true => {
+                let _ = self.remove(id);
+                None
+            },
+            false => Some(data),
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData) -> Result<(), Error> {
+        let path = self.path(id)?;
+        // Write to a temporary file first so readers never see half a session.
+        // Each write has its own, so concurrent saves can't mix their content.
+        let tmp = self.directory.join(format!("{}.{}.tmp", id, random::token(8)));
+        let written = OpenOptions::new().write(true).create_new(true).open(&tmp)
+            .and_then(|mut file| file.write_all(data.to_json().to_string().as_bytes()))
+            .and_then(|_| fs::rename(&tmp, path));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written
+    }
+
+    fn remove(&self, id: &str) -> Result<(), Error> {
+        match fs::remove_file(self.path(id)?) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    fn purge_expired(&self) {
+        let Ok(entries) = fs::read_dir(&self.directory) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    // Loading removes the file when the session expired
+                    self.load(id);
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_session_store {
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    use serde_json::json;
+
+    use super::{FileStore, MemoryStore, SessionData, SessionStore};
+
+    fn check_store(store: &dyn SessionStore) {
+        let data = SessionData {
+            values: HashMap::from([("user".to_string(), json!(42))]),
+            expires: SystemTime::now() + Duration::from_secs(60),
+        };
+        store.save("abc", &data).unwrap();
+        assert_eq!(store.load("abc").unwrap().values, data.values);
+
+        let expired = SessionData { expires: SystemTime::now() - Duration::from_secs(1), ..data };
+        store.save("old", &expired).unwrap();
+        assert_eq!(store.load("old"), None);
+
+        store.remove("abc").unwrap();
+        assert_eq!(store.load("abc"), None);
+    }
+
+    #[test]
+    fn memory_store() {
+        check_store(&MemoryStore::new());
+    }
+
+    #[test]
+    fn file_store() {
+        let directory = std::env::temp_dir().join(format!("myapi-sessions-{}", std::process::id()));
+        let store = FileStore::new(directory.to_str().unwrap()).unwrap();
+        check_store(&store);
+
+        // Concurrent saves of a session never leave a mix of both, nor temporary files
+        let values = |n: u32| SessionData {
+            values: HashMap::from([("n".to_string(), json!(n)), ("copy".to_string(), json!(n))]),
+            expires: SystemTime::now() + Duration::from_secs(60),
+        };
+        std::thread::scope(|scope| {
+            for n in 0..8 {
+                let store = &store;
+                scope.spawn(move || (0..20).for_each(|_| store.save("shared", &values(n)).unwrap()));
+            }
+        });
+        let saved = store.load("shared").unwrap();
+        assert_eq!(saved.values["n"], saved.values["copy"]);
+        let leftovers = std::fs::read_dir(&directory).unwrap().flatten()
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "tmp"))
+            .count();
+        assert_eq!(leftovers, 0);
+        assert!(store.save("../escape", &SessionData { values: HashMap::new(), expires: SystemTime::now() }).is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
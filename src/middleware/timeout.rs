@@ -0,0 +1,93 @@
+// Deadline for the handlers of a request
+
+use std::time::{Duration, Instant};
+
+use crate::http::{request::Request, response::Response, status::HttpStatus};
+use crate::middleware::{Middleware, Next};
+
+/// Cancels async handlers that run past a deadline.
+///
+/// The deadline starts when the layer runs. Handlers registered with `async_handler`
+/// are dropped once it passes and the request is answered with `503 Service Unavailable`,
+/// or the status given with `status`. Plain handlers can't be interrupted; they are
+/// only logged when they take longer than the timeout.
+///
+/// A route layer replaces the deadline of the app or router layers, so a
+/// single route can get more or less time than the rest.
+///
+/// # Example
+///
+///
+/// app.layer(Timeout::new(Duration::from_secs(10)));
+///
+/// app.get("/export", async_handler(export))
+///     .layer(Timeout::new(Duration::from_secs(60)).status(HttpStatus::GatewayTimeout));
+///
+pub struct Timeout {
+    duration: Duration,
+    status: HttpStatus,
+}
+
+impl Timeout {
+    pub fn new(duration: Duration) -> Self {
+        Timeout { duration, status: HttpStatus::ServiceUnavailable }
+    }
+
+    /// Status of the response sent when the deadline passes (503 by default)
+    pub fn status(mut self, status: HttpStatus) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+impl Middleware for Timeout {
+    fn handle(&self, req: &mut Request, res: &mut Response, next: Next<'_>) {
+        let start = Instant::now();
+        req.set_deadline(start + self.duration, self.status);
+        next.run(req, res);
+
+        if start.elapsed() > self.duration && res.get_status() != &self.status {
+            println!("Handler of {} {} (route {}) ran past its timeout of {:?}", req.get_method(), req.get_uri(), req.get_route().unwrap_or("none"), self.duration);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test_timeout {
+    use std::time::{Duration, Instant};
+
+    use super::Timeout;
+    use crate::http::into_response::async_handler;
+    use crate::http::{request::Request, response::Response, status::HttpStatus};
+    use crate::middleware::{Middleware, Next};
+
+    fn run(layer: &Timeout, delay: Duration) -> Response {
+        let mut req = Request::new("GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut res = Response::empty();
+        let action = async_handler(move |_req| async move {
+            tokio::time::sleep(delay).await;
+            "done"
+        });
+        let endpoint = |req: &mut Request, res: &mut Response| {
+            res.set_status(HttpStatus::Ok);
+            action(req, res);
+        };
+        layer.handle(&mut req, &mut res, Next::new(&[], &endpoint));
+        res
+    }
+
+    #[test]
+    fn cancels_slow_handlers() {
+        let layer = Timeout::new(Duration::from_millis(50));
+        assert_eq!(run(&layer, Duration::ZERO).get_body().unwrap(), b"done");
+
+        let start = Instant::now();
+        let res = run(&layer, Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(res.get_status(), &HttpStatus::ServiceUnavailable);
+
+        let layer = layer.status(HttpStatus::GatewayTimeout);
+        assert_eq!(run(&layer, Duration::from_secs(10)).get_status(), &HttpStatus::GatewayTimeout);
+    }
+}
@@ -1,15 +1,20 @@
 // Route structure for handling routes in the server api
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use regex::Regex;
 
 use crate::http::{request::Request, response::Response};
+use crate::middleware::{Layers, Middleware};
 use crate::utils::formatter;
 
+// Shared by the clones of the route, and called by several requests at once
+pub(crate) type Handler = Arc<dyn for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync>;
+
 pub struct Route {
     uri: String,
-    action: Arc<Mutex<Box<dyn for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response>>>,
+    action: Handler,
     parameters: Vec<String>,
     regex: Regex,
+    layers: Layers,
 }
 
 
@@ -26,21 +31,22 @@ impl Clone for Route {
             action: self.action.clone(),
             parameters: self.parameters.clone(),
             regex: self.regex.clone(),
+            layers: self.layers.clone(),
         }
     }
 }
 
 impl Route {
-    pub fn new(uri: &str, action: Box<dyn for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response>) -> Self {
+    pub fn new(uri: &str, action: Handler) -> Self {
         let mut params = Vec::new();
         let regex_str = formatter::format_regex(uri, &mut params);
         let regex = Regex::new(&regex_str).expect("Error creating regex");
         Route {
             uri: uri.to_string(),
-            action: Arc::new(Mutex::new(action)),
+            action,
             parameters: params,
             regex,
-            // middlewares: Vec::new(),
+            layers: Vec::new(),
         }
     }
 
@@ -52,7 +58,7 @@ impl Route {
         &self.regex
     }
 
-    pub fn get_action(&self) -> Arc<Mutex<Box<dyn for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response>>> {
+    pub fn get_action(&self) -> Handler {
         self.action.clone()
     }
 
@@ -65,4 +71,22 @@ impl Route {
     pub fn get_parameters(&self) -> &Vec<String> {
         &self.parameters
     }
+
+    /// Add a middleware layer that only runs for this route.
+    /// Route layers run after the layers of the router.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.get("/search", |_req, res| res)
+    ///     .layer(RateLimit::new(10, Duration::from_secs(1)));
+    /// 
+    pub fn layer<M: Middleware + 'static>(&mut self, middleware: M) -> &mut Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    pub(crate) fn get_layers(&self) -> &Layers {
+        &self.layers
+    }
 }
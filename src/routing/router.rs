@@ -1,20 +1,23 @@
 // Router structure for handling routes in the server api
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::routing::route::Route;
+use crate::routing::route::{Handler, Route};
+use crate::middleware::{Layers, Middleware, Next};
 use crate::http::{methods::HttpMethod,request::Request,response::Response, status::HttpStatus};
+use crate::http::extensions::Extensions;
 
 // TODO: Implement my own HashMap
 pub struct Router {
     routes: HashMap<String, Vec<Route>>,
     num_routes: usize,
-    path: String
+    path: String,
+    layers: Layers,
+    fallback: Option<Route>,
+    state: Extensions,
 }
 
-unsafe impl Send for Router {}
-unsafe impl Sync for Router {}
-
 impl std::fmt::Debug for Router {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Router {{ routes: {:?}, num_routes: {} }}", self.routes, self.num_routes)
@@ -27,10 +30,19 @@ impl Clone for Router {
             routes: self.routes.clone(),
             num_routes: self.num_routes,
             path: self.path.clone(),
+            layers: self.layers.clone(),
+            fallback: self.fallback.clone(),
+            state: self.state.clone(),
         }
     }
 }
 
+impl Default for Router {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
 
 
 impl Router {
@@ -48,17 +60,49 @@ impl Router {
         Router {
             routes: methods,
             num_routes: 0,
-            path: "".to_string()
+            path: "".to_string(),
+            layers: Vec::new(),
+            fallback: None,
+            state: Extensions::new(),
         }
     }
     
     // Private method to create a new Route
-    fn register_route(&mut self, method: HttpMethod, path: &str, action: Box<dyn for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static>) {
+    fn register_route(&mut self, method: HttpMethod, path: &str, action: Handler) -> &mut Route {
         let method = method.to_string();
         let formated_path = format!("{}{}", self.path, path);
         println!("Registering route: {} {}", method, formated_path);
         let route = Route::new(&formated_path, action);
-        self.routes.entry(method).or_insert(Vec::new()).push(route);
+        let routes = self.routes.entry(method).or_default();
+        routes.push(route);
+        routes.last_mut().unwrap()
+    }
+
+    /// Add a middleware layer that runs for every route of this router
+    ///
+    /// # Example
+    ///
+    /// 
+    /// let admin = app.router("admin");
+    /// admin.layer(RateLimit::new(100, Duration::from_secs(60)));
+    /// 
+    pub fn layer<M: Middleware + 'static>(&mut self, middleware: M) -> &mut Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    /// Share a value with the layers and handlers of this router, like `App::with_state`.
+    /// It takes precedence over the state of the app with the same type.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// let admin = app.router("admin");
+    /// admin.with_state(AuditLog::open("audit.log")?);
+    /// 
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, state: T) -> &mut Self {
+        self.state.insert(state);
+        self
     }
 
     //Resolvers
@@ -77,33 +121,116 @@ impl Router {
                     params.insert(name.to_string(), value);
                 }
                 req.set_params(&params);
+                req.set_route(route.get_uri());
                 return Some(route);
             }
         }
         None
     }
 
+    // Methods with a route matching the uri, for the Allow header of 405 responses
+    pub(crate) fn allowed_methods(&self, uri: &str) -> Vec<String> {
+        self.routes.iter()
+            .filter(|(_, routes)| routes.iter().any(|route| route.get_regex().is_match(uri)))
+            .map(|(method, _)| method.clone())
+            .collect()
+    }
+
     //    
     //  This method will resolve the request and return a response, by checking
     //  the existance of the route in the routes HashMap of the Aplication server.        //
     //     -->  It will return an empty response if the route is not found.
     //
     pub fn resolve(&self, req: &mut Request) -> Response { 
+        let mut res = Response::empty();
+        self.dispatch(req, &mut res);
+        res
+    }
+
+    // Whether a route of this router matches the method and the uri of the request
+    pub(crate) fn matches(&self, req: &Request) -> bool {
+        self.routes.get(&req.get_method().to_string())
+            .is_some_and(|routes| routes.iter().any(|route| route.get_regex().is_match(req.get_uri())))
+    }
 
-        let route = self.resolve_route(req);
-        match route {
-            Some(r) => {
-                let mut res = Response::empty();
-                res.set_status(HttpStatus::Ok);
-                let action = r.get_action();
-                let action = action.lock().unwrap();
-                action(req, &mut res);
-                res
+    // Run the matching route, and its layers, on the given response.
+    // When routes match the uri but none for the method of the request, the layers
+    // of the router run around a `405 Method Not Allowed` response.
+    // Returns false if no route of this router matches the uri.
+    pub(crate) fn dispatch(&self, req: &mut Request, res: &mut Response) -> bool {
+        if let Some(route) = self.resolve_route(req) {
+            res.set_status(HttpStatus::Ok);
+            self.run_route(route, req, res);
+            return true;
+        }
+        let mut allowed = self.allowed_methods(req.get_uri());
+        if allowed.is_empty() {
+            return false;
+        }
+        allowed.sort();
+        let endpoint = |_req: &mut Request, res: &mut Response| {
+            res.error(HttpStatus::MethodNotAllowed);
+            res.set_header("Allow", &allowed.join(", "));
+        };
+        req.add_state(&self.state);
+        Next::new(&self.layers, &endpoint).run(req, res);
+        true
+    }
+
+    // Run the fallback of the router, if it has one and the request is under its path.
+    // Returns false if the fallback was not run.
+    pub(crate) fn dispatch_fallback(&self, req: &mut Request, res: &mut Response) -> bool {
+        match &self.fallback {
+            Some(fallback) if self.contains(req.get_uri()) => {
+                res.set_status(HttpStatus::NotFound);
+                self.run_route(fallback, req, res);
+                true
             },
-            None => Response::empty(),
+            _ => false,
         }
     }
 
+    pub(crate) fn has_fallback(&self) -> bool {
+        self.fallback.is_some()
+    }
+
+    // Whether the uri is under the path of the router
+    pub(crate) fn contains(&self, uri: &str) -> bool {
+        let path = uri.split('?').next().unwrap_or_default();
+        match path.strip_prefix(&self.path) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || self.path.is_empty(),
+            None => false,
+        }
+    }
+
+    fn run_route(&self, route: &Route, req: &mut Request, res: &mut Response) {
+        let action = route.get_action();
+        let endpoint = |req: &mut Request, res: &mut Response| {
+            action(req, res);
+        };
+        req.add_state(&self.state);
+        let layers: Layers = self.layers.iter().chain(route.get_layers()).cloned().collect();
+        Next::new(&layers, &endpoint).run(req, res);
+    }
+
+    /// Set the handler for the requests under the path of the router that match
+    /// none of its routes. The response starts as a `404 Not Found` response and
+    /// the layers of the router run as for any other route.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// let admin = app.router("admin");
+    /// admin.fallback(|_req, res| {
+    ///     res.render_template("admin_not_found.html", HashMap::new())
+    /// });
+    /// 
+    pub fn fallback<F>(&mut self, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
+    {
+        self.fallback.insert(Route::new(&self.path, Arc::new(action)))
+    }
+
     pub fn mount(&mut self, path: &str) {
         self.path = path.to_string();
     }
@@ -118,46 +245,46 @@ impl Router {
     
     // PUBLIC API METHODS
 
-    pub fn store_route<F>(&mut self, method: HttpMethod, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn store_route<F>(&mut self, method: HttpMethod, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.register_route(method, path, Box::new(action));
+        self.register_route(method, path, Arc::new(action))
     }
 
-    pub fn get<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn get<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.register_route(HttpMethod::GET, path, Box::new(action));
+        self.register_route(HttpMethod::GET, path, Arc::new(action))
     }
 
-    pub fn post<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn post<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.register_route(HttpMethod::POST, path, Box::new(action));
+        self.register_route(HttpMethod::POST, path, Arc::new(action))
     }
 
-    pub fn put<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn put<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.register_route(HttpMethod::PUT, path, Box::new(action));
+        self.register_route(HttpMethod::PUT, path, Arc::new(action))
     }
 
-    pub fn delete<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn delete<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.register_route(HttpMethod::DELETE, path, Box::new(action));
+        self.register_route(HttpMethod::DELETE, path, Arc::new(action))
     }
 
-    pub fn options<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn options<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.register_route(HttpMethod::OPTIONS, path, Box::new(action));
+        self.register_route(HttpMethod::OPTIONS, path, Arc::new(action))
     }
 
-    pub fn patch<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn patch<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.register_route(HttpMethod::PATCH, path, Box::new(action));
+        self.register_route(HttpMethod::PATCH, path, Arc::new(action))
     }
 
 }
@@ -222,6 +349,30 @@ mod test_router {
         assert_eq!(res.get_status().to_string(), "404 Not Found");
     }
 
+    #[test]
+    fn route_rate_limit() {
+        use std::time::Duration;
+        use crate::http::request::Request;
+        use crate::middleware::rate_limit::RateLimit;
+        use crate::routing::router::Router;
+
+        let mut router = Router::new();
+        router.get("/limited", |_req, res| res)
+            .layer(RateLimit::new(1, Duration::from_secs(60)));
+        router.get("/free", |_req, res| res);
 
+        let req_str = "GET /limited HTTP/1.1\r\nHost: localhost:4221\r\n\r\n";
+        let res = router.resolve(&mut Request::new(req_str).unwrap());
+        assert_eq!(res.get_status().to_string(), "200 OK");
+        assert_eq!(res.get_header("RateLimit-Remaining").unwrap(), "0");
+
+        let res = router.resolve(&mut Request::new(req_str).unwrap());
+        assert_eq!(res.get_status().to_string(), "429 Too Many Requests");
+        assert_eq!(res.get_header("Retry-After").unwrap(), "60");
+
+        let req_str = "GET /free HTTP/1.1\r\nHost: localhost:4221\r\n\r\n";
+        let res = router.resolve(&mut Request::new(req_str).unwrap());
+        assert_eq!(res.get_status().to_string(), "200 OK");
+    }
 
 }
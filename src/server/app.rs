@@ -1,19 +1,47 @@
 extern crate tokio;
 
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use std::any::Any;
 use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
 use std::collections::HashMap;
 
 use crate::http::methods::HttpMethod;
-use crate::routing::router::Router;
+use crate::middleware::{Layers, Middleware, Next};
+use crate::routing::{route::Route, router::Router};
 use crate::config;
+use crate::utils::parsers;
 use crate::http::{request::Request, response::Response};
 use crate::http::status::HttpStatus;
+#[cfg(feature = "json")]
+use crate::http::{problem::Problem, error::Error, into_response::IntoResponse};
+use crate::http::forwarded::{self, ProxyHeader};
+use crate::utils::cidr::{self, Cidr};
+use crate::http::extensions::Extensions;
+use crate::server::proxy_protocol::{self, Preamble};
+
+// Largest request head (request line and headers) accepted
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+const DEFAULT_BODY_LIMIT: usize = 1024 * 1024;
+
+type ErrorHandler = Arc<dyn for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync>;
 
 pub struct App {
     address: SocketAddr,
     routers: HashMap<String, Router>, 
+    layers: Layers,
+    panic_handler: Option<ErrorHandler>,
+    error_handlers: HashMap<u16, ErrorHandler>,
+    #[cfg(feature = "json")]
+    problem_details: bool,
+    trusted_proxies: Vec<Cidr>,
+    proxy_header: ProxyHeader,
+    proxy_protocol: bool,
+    body_limit: usize,
+    state: Extensions,
+    server_header: Option<String>,
 }
 
 impl Clone for App {
@@ -21,6 +49,17 @@ impl Clone for App {
         App {
             address: self.address,
             routers: self.routers.clone(),
+            layers: self.layers.clone(),
+            panic_handler: self.panic_handler.clone(),
+            error_handlers: self.error_handlers.clone(),
+            #[cfg(feature = "json")]
+            problem_details: self.problem_details,
+            trusted_proxies: self.trusted_proxies.clone(),
+            proxy_header: self.proxy_header,
+            proxy_protocol: self.proxy_protocol,
+            body_limit: self.body_limit,
+            state: self.state.clone(),
+            server_header: self.server_header.clone(),
         }
     }
 }
@@ -43,16 +82,16 @@ impl App {
         let listener = TcpListener::bind(&self.address).await.expect("Failed to bind to address");
         println!("Listening on {}:{}", config::HOST, config::PORT);
 
-        let arc = Arc::new(self.routers.clone());
+        let arc = Arc::new(self.clone());
 
         // Event loop for receiving requests
         loop {
-            let (stream, _) = listener.accept().await.expect("Failed to accept connection");
+            let (stream, peer) = listener.accept().await.expect("Failed to accept connection");
             let cloned = Arc::clone(&arc);
                 
             // Spawn a new thread to handle the connection
             tokio::spawn(async move {
-                App::handle_connection(cloned, stream);
+                App::handle_connection(cloned, stream, peer).await;
             });
         }
     }
@@ -74,10 +113,190 @@ impl App {
                 let mut map = HashMap::new();
                 map.insert("default".to_string(), router);
                 map
-            }
+            },
+            layers: Vec::new(),
+            panic_handler: None,
+            error_handlers: HashMap::new(),
+            #[cfg(feature = "json")]
+            problem_details: false,
+            trusted_proxies: Vec::new(),
+            proxy_header: ProxyHeader::XForwarded,
+            proxy_protocol: false,
+            body_limit: DEFAULT_BODY_LIMIT,
+            state: Extensions::new(),
+            server_header: None,
         }
     }
 
+    /// Add a middleware layer that runs for every request received by the server,
+    /// before the request is dispatched to the routers
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.layer(RateLimit::new(100, Duration::from_secs(60)));
+    /// 
+    pub fn layer<M: Middleware + 'static>(&mut self, middleware: M) -> &mut Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    /// Share a value, like a database pool or the configuration, with every layer and
+    /// handler of the app. It is kept in an `Arc`, so it is not cloned per connection
+    /// and needs its own synchronization (e.g. a `Mutex`) to be modified.
+    /// There is one value per type, setting another value of the same type replaces it.
+    ///
+    /// Handlers get it with the `State` extractor or `req.state()`.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// struct Db { users: Mutex<Vec<String>> }
+    ///
+    /// app.with_state(Db { users: Mutex::new(Vec::new()) });
+    /// app.get("/users", extract(|State(db): State<Db>| db.users.lock().unwrap().join(", ")));
+    /// 
+    pub fn with_state<T: Send + Sync + 'static>(&mut self, state: T) -> &mut Self {
+        self.state.insert(state);
+        self
+    }
+
+    /// Set the handler that builds the response when a handler or a middleware panics.
+    /// The response it receives is a `500 Internal Server Error` response that keeps
+    /// the headers set by the layers, but not the status, body or content headers of the handler.
+    /// By default the body is just "Internal Server Error".
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.on_panic(|_req, res| {
+    ///     res.render_template("500.html", HashMap::new())
+    /// });
+    /// 
+    pub fn on_panic<F>(&mut self, handler: F)
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static
+    {
+        self.panic_handler = Some(Arc::new(handler));
+    }
+
+    /// Set the handler that builds the error responses with the given status.
+    ///
+    /// It is called for the errors produced by the framework and its layers (no matching
+    /// route, failed authentication, rate limiting...) and for the `Error`s returned by
+    /// handlers, whose message is available with `res.get_error_detail()`. The response
+    /// it receives keeps the status and the headers already set, like `WWW-Authenticate`
+    /// or `Retry-After`. Responses built by handlers are left untouched.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.on_error(HttpStatus::Unauthorized, |_req, res| {
+    ///     res.render_template("401.html", HashMap::new())
+    /// });
+    /// 
+    pub fn on_error<F>(&mut self, status: HttpStatus, handler: F)
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static
+    {
+        self.error_handlers.insert(status.code(), Arc::new(handler));
+    }
+
+    /// Set the handler that builds the `404 Not Found` response
+    /// when no route and no router fallback matches the request
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.not_found(|req, res| {
+    ///     res.send(&format!("Nothing at {}", req.get_uri()))
+    /// });
+    /// 
+    pub fn not_found<F>(&mut self, handler: F)
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static
+    {
+        self.on_error(HttpStatus::NotFound, handler);
+    }
+
+    /// Answer the errors produced by the framework and its layers (400, 404, 405,
+    /// 413, 500...) with an `application/problem+json` body instead of plain text.
+    /// The message of the client errors returned by handlers becomes the `detail`.
+    /// Handlers registered with `on_error` still take precedence.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.problem_details(true);
+    /// // GET /nowhere -> {"type":"about:blank","title":"Not Found","status":404,"instance":"/nowhere"}
+    /// 
+    #[cfg(feature = "json")]
+    pub fn problem_details(&mut self, enabled: bool) -> &mut Self {
+        self.problem_details = enabled;
+        self
+    }
+
+    /// Set the reverse proxies, as IPv4/IPv6 ranges in CIDR notation, and the header
+    /// they write the client into: `Forwarded` or `X-Forwarded-For`/`-Proto`/`-Host`.
+    ///
+    /// For requests coming from them, `req.remote_addr()`, `req.scheme()` and `req.host()`
+    /// return what the client used, so layers like `RateLimit` and `IpFilter` see the real
+    /// client. The headers of other peers are ignored, and so is the other header, which
+    /// the proxies pass along as the client sent it.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.trusted_proxies(&["10.0.0.0/8", "fd00::/8"], ProxyHeader::XForwarded)?;
+    /// 
+    pub fn trusted_proxies(&mut self, ranges: &[&str], header: ProxyHeader) -> Result<&mut Self, std::io::Error> {
+        self.trusted_proxies = cidr::parse_list(ranges)?;
+        self.proxy_header = header;
+        Ok(self)
+    }
+
+    /// Expect every connection to start with a PROXY protocol header (versions 1 and 2),
+    /// as sent by HAProxy and most TCP load balancers. The source and destination
+    /// addresses it carries become `req.peer_addr()` and `req.local_addr()`.
+    /// Connections without a valid header are closed without a response.
+    ///
+    /// Only enable it when every connection comes through such a load balancer,
+    /// otherwise clients could send the header themselves.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.proxy_protocol(true);
+    /// app.run().await;
+    /// 
+    pub fn proxy_protocol(&mut self, enabled: bool) -> &mut Self {
+        self.proxy_protocol = enabled;
+        self
+    }
+
+    /// Largest request body accepted, 1 MiB by default.
+    /// Requests announcing a bigger body are answered with `413 Payload Too Large`.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.body_limit(20 * 1024 * 1024);
+    /// 
+    pub fn body_limit(&mut self, bytes: usize) -> &mut Self {
+        self.body_limit = bytes;
+        self
+    }
+
+    /// Send a `Server` header with every response, none is sent by default.
+    /// Responses that set their own keep it.
+    ///
+    /// # Example
+    ///
+    /// 
+    /// app.server_header("myapi");
+    /// 
+    pub fn server_header(&mut self, value: &str) -> &mut Self {
+        self.server_header = Some(value.to_string());
+        self
+    }
+
     pub fn router(&mut self, name: &str) -> &mut Router {
         self.routers.insert(name.to_string(), Router::new());
         let router = self.routers.get_mut(name).unwrap();
@@ -86,44 +305,247 @@ impl App {
         router
     } 
 
-    fn handle_connection(cloned: Arc<HashMap<String, Router>>, stream: TcpStream) {
-        let mut buffer = [0; 1024];
+    async fn handle_connection(app: Arc<App>, mut stream: TcpStream, peer: SocketAddr) {
+        let mut data = Vec::new();
+        let mut peer = peer;
+        let mut local = stream.local_addr().ok();
 
-        // Try reading from the stream until it is successful
-        while !stream.try_read(&mut buffer).is_ok() {}
+        if app.proxy_protocol {
+            loop {
+                match proxy_protocol::parse(&data) {
+                    Ok(Preamble::Complete(header, length)) => {
+                        data.drain(..length);
+                        peer = header.source.unwrap_or(peer);
+                        local = header.destination.or(local);
+                        break;
+                    },
+                    Ok(Preamble::Incomplete) => {
+                        if !App::read_more(&mut stream, &mut data, peer).await {
+                            return;
+                        }
+                    },
+                    Err(e) => {
+                        println!("Rejected connection from {}: {}", peer, e);
+                        return;
+                    },
+                }
+            }
+        }
 
-        let request = String::from_utf8_lossy(&buffer[..]);
+        // Wait for the whole request
+        let request = match app.read_request(&mut stream, data, peer).await {
+            Ok(request) => request,
+            Err(Some(status)) => {
+                let mut res = Response::empty();
+                res.error(status);
+                app.handle_error(&Request::empty(), &mut res);
+                app.write_response(&mut stream, &mut res, peer).await;
+                println!("[{:} {:}] Rejected request from {}", res.get_version(), res.get_status(), peer);
+                return;
+            },
+            Err(None) => return,
+        };
 
-        // Create a new Request object
-        let mut req = Request::new(&request);
+        // Handlers and layers are blocking code, run them off the async workers
+        let handling = app.clone();
+        let handled = tokio::task::spawn_blocking(move || {
+            // Create a new Request object
+            let mut req = Request::from_bytes(&request);
 
-        let mut res = Response::empty();
-        
-        match &mut req {
-            Ok(req) => {
-                // Resolve the request
-                for router in cloned.iter() {
-                    let response = router.1.resolve(&mut req.clone());
-                    if !response.get_status().eq(&HttpStatus::NotFound) {
-                        res = response;
-                        break;
+            let mut res = Response::empty();
+
+            match &mut req {
+                Ok(req) => {
+                    req.set_peer_addr(peer);
+                    if let Some(local) = local {
+                        req.set_local_addr(local);
                     }
+                    res = handling.process(req);
+                },
+                Err(_) => {
+                    // Set the status to BadRequest if the request is invalid
+                    res.error(HttpStatus::BadRequest);
+                    handling.handle_error(&Request::empty(), &mut res);
                 }
+            }
+            (req, res)
+        }).await;
+
+        let (req, mut res) = match handled {
+            Ok(handled) => handled,
+            Err(e) => {
+                println!("Error handling request from {}: {}", peer, e);
+                let mut res = Response::empty();
+                res.error(HttpStatus::InternalServerError);
+                (Err(std::io::Error::other("request task failed")), res)
+            }
+        };
+
+        app.write_response(&mut stream, &mut res, peer).await;
+
+        // Print debug information
+        println!("[{:} {:}] Number of bytes: {:?}",res.get_version(), res.get_status(), req.map(|req| req.to_string().len()).unwrap_or(0));
+
+    }
+
+
+    // Send the response, the connection is closed once it is written
+    async fn write_response(&self, stream: &mut TcpStream, res: &mut Response, peer: SocketAddr) {
+        if let Some(server) = &self.server_header {
+            if res.get_header("Server").is_none() {
+                res.set_header("Server", server);
+            }
+        }
+        res.set_header("Connection", "close");
+        if let Err(e) = stream.write_all(&res.to_bytes()).await {
+            println!("Error writing to {}: {}", peer, e);
+        }
+    }
+
+    // Read the head of the request and the body announced by its Content-Length.
+    // Fails with the status of the error response, or None if the connection was closed.
+    async fn read_request(&self, stream: &mut TcpStream, mut data: Vec<u8>, peer: SocketAddr) -> Result<Vec<u8>, Option<HttpStatus>> {
+        let head_length = loop {
+            if let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+            if data.len() > MAX_HEAD_SIZE {
+                return Err(Some(HttpStatus::BadRequest));
+            }
+            if !App::read_more(stream, &mut data, peer).await {
+                return Err(None);
+            }
+        };
+
+        let body_length = parsers::content_length(&data[..head_length]).map_err(|_| Some(HttpStatus::BadRequest))?;
+        if body_length > self.body_limit {
+            return Err(Some(HttpStatus::PayloadTooLarge));
+        }
+        let length = head_length + body_length;
+        while data.len() < length {
+            if !App::read_more(stream, &mut data, peer).await {
+                return Err(None);
+            }
+        }
+        data.truncate(length);
+        Ok(data)
+    }
+
+    // Read the next chunk of the connection, false once it is closed
+    async fn read_more(stream: &mut TcpStream, data: &mut Vec<u8>, peer: SocketAddr) -> bool {
+        let mut buffer = [0; 1024];
+        match stream.read(&mut buffer).await {
+            Ok(0) => false,
+            Ok(read) => {
+                data.extend_from_slice(&buffer[..read]);
+                true
             },
-            Err(_) => {
-                // Set the status to BadRequest if the request is invalid
-                res.set_status(HttpStatus::BadRequest)
+            Err(e) => {
+                println!("Error reading from {}: {}", peer, e);
+                false
             }
         }
+    }
 
-        // Try writing to the stream until it is successful
-        while !stream.try_write(res.to_string().as_bytes()).is_ok() {}
+    // Run the request through the app layers and the routers
+    pub(crate) fn process(&self, req: &mut Request) -> Response {
+        if !self.trusted_proxies.is_empty() {
+            forwarded::resolve(req, &self.trusted_proxies, self.proxy_header);
+        }
+        req.add_state(&self.state);
+        let mut res = Response::empty();
+        let dispatch = |req: &mut Request, res: &mut Response| {
+            // Resolve the request with the first router that has a matching route,
+            // or else with the first one having a route for another method (405).
+            // Panics are caught here so the app layers still see the 500 response.
+            let matched = panic::catch_unwind(AssertUnwindSafe(|| {
+                let router = self.routers.values().find(|router| router.matches(req))
+                    .or_else(|| self.routers.values().find(|router| !router.allowed_methods(req.get_uri()).is_empty()));
+                match router {
+                    Some(router) => router.dispatch(req, res),
+                    None => self.fallback(req, res),
+                }
+            }));
+            match matched {
+                Ok(true) => {},
+                Ok(false) => {
+                    res.error(HttpStatus::NotFound);
+                },
+                Err(payload) => self.recover(req, res, payload),
+            }
+        };
+        // Catch panics of the app layers too
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            Next::new(&self.layers, &dispatch).run(req, &mut res);
+        }));
+        if let Err(payload) = result {
+            self.recover(req, &mut res, payload);
+        }
+        self.handle_error(req, &mut res);
+        res
+    }
 
-        // Print debug information
-        println!("[{:} {:}] Number of bytes: {:?}",res.get_version(), res.get_status().to_string(), req.expect("0").to_string().len());
+    // Run the fallback of the most specific router whose path contains the request
+    fn fallback(&self, req: &mut Request, res: &mut Response) -> bool {
+        let router = self.routers.values()
+            .filter(|router| router.has_fallback() && router.contains(req.get_uri()))
+            .max_by_key(|router| router.get_path().len());
+        match router {
+            Some(router) => router.dispatch_fallback(req, res),
+            None => false,
+        }
+    }
 
+    // Let the error handler of the status rebuild a default error response
+    fn handle_error(&self, req: &Request, res: &mut Response) {
+        if !res.is_default_error() {
+            return;
+        }
+        if let Some(handler) = self.error_handlers.get(&res.get_status().code()) {
+            let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                handler(req, res);
+            }));
+            if handled.is_err() {
+                println!("Panic in the error handler for {}", res.get_status());
+                res.clear_content();
+                res.error(HttpStatus::InternalServerError);
+            }
+        }
+        #[cfg(feature = "json")]
+        if self.problem_details && res.is_default_error() {
+            let error = match res.get_error_detail() {
+                Some(detail) => Error::with_message(*res.get_status(), detail),
+                None => Error::new(*res.get_status()),
+            };
+            let mut problem = Problem::from(error);
+            if !req.get_uri().is_empty() {
+                problem = problem.instance(req.get_uri());
+            }
+            problem.into_response(res);
+        }
     }
 
+    // Turn a panic into a 500 response, built by the panic handler if there is one
+    fn recover(&self, req: &Request, res: &mut Response, payload: Box<dyn Any + Send>) {
+        let message = payload.downcast_ref::<&str>().copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        println!("Panic while handling {} {} (route {}): {}", req.get_method(), req.get_uri(), req.get_route().unwrap_or("none"), message);
+
+        // The headers of the layers that already ran are kept
+        res.clear_content();
+        res.error(HttpStatus::InternalServerError);
+        if let Some(handler) = &self.panic_handler {
+            // A panicking panic handler must not take the connection down either
+            let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                handler(req, res);
+            }));
+            if handled.is_err() {
+                res.clear_content();
+                res.error(HttpStatus::InternalServerError);
+            }
+        }
+    }
 
     /// Method to store a GET route in the server
     /// Takes the path and a closure as arguments.
@@ -137,10 +559,10 @@ impl App {
     /// res
     /// });
     /// 
-    pub fn get<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn get<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.routers.get_mut("default").unwrap().store_route(HttpMethod::GET, path, action);
+        self.routers.get_mut("default").unwrap().store_route(HttpMethod::GET, path, action)
     }
 
     /// Method to store a POST route in the server
@@ -155,10 +577,10 @@ impl App {
     /// res
     /// });
     /// 
-    pub fn post<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn post<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.routers.get_mut("default").unwrap().store_route(HttpMethod::POST, path, action);
+        self.routers.get_mut("default").unwrap().store_route(HttpMethod::POST, path, action)
     }
 
     /// Method to store a PUT route in the server
@@ -173,10 +595,10 @@ impl App {
     /// res
     /// });
     /// 
-    pub fn put<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn put<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.routers.get_mut("default").unwrap().store_route(HttpMethod::PUT, path, action);
+        self.routers.get_mut("default").unwrap().store_route(HttpMethod::PUT, path, action)
     }
 
     /// Method to store a DELETE route in the server
@@ -191,10 +613,10 @@ impl App {
     /// res
     /// });
     /// 
-    pub fn delete<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn delete<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.routers.get_mut("default").unwrap().store_route(HttpMethod::DELETE, path, action);
+        self.routers.get_mut("default").unwrap().store_route(HttpMethod::DELETE, path, action)
     }
 
     /// Method to store a OPTIONS route in the server
@@ -209,10 +631,10 @@ impl App {
     /// res
     /// });
     /// 
-    pub fn options<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn options<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.routers.get_mut("default").unwrap().store_route(HttpMethod::OPTIONS, path, action);
+        self.routers.get_mut("default").unwrap().store_route(HttpMethod::OPTIONS, path, action)
     }
 
     /// Method to store a PATCH route in the server
@@ -227,11 +649,336 @@ impl App {
     /// res
     /// });
     /// 
-    pub fn patch<F>(&mut self, path: &str, action: F) 
-        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + 'static 
+    pub fn patch<F>(&mut self, path: &str, action: F) -> &mut Route
+        where F: for<'a> Fn(&'a Request, &'a mut Response) -> &'a mut Response + Send + Sync + 'static 
     {
-        self.routers.get_mut("default").unwrap().store_route(HttpMethod::PATCH, path, action);
+        self.routers.get_mut("default").unwrap().store_route(HttpMethod::PATCH, path, action)
     }
 
 
 }
+
+
+#[cfg(test)]
+mod test_app {
+    use crate::http::error::Error;
+    use crate::http::extract::{extract, Path, Query, State};
+    use crate::http::forwarded::ProxyHeader;
+    use crate::http::into_response::{async_handler, handler};
+    use crate::http::request::Request;
+    use crate::http::status::HttpStatus;
+    use crate::http::response::Response;
+    use crate::middleware::auth::BasicAuth;
+    use crate::middleware::{Middleware, Next};
+    use crate::server::app::App;
+
+    fn get(app: &App, uri: &str) -> String {
+        let mut req = Request::new(&format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", uri)).unwrap();
+        let res = app.process(&mut req);
+        format!("{} {}", res.get_status(), String::from_utf8_lossy(res.get_body().unwrap()))
+    }
+
+    #[test]
+    fn panic_isolation() {
+        let mut app = App::new("127.0.0.1", 4221);
+        app.get("/divide/:n", |req, res| {
+            let n: u32 = req.get_param("n").unwrap().parse().unwrap();
+            res.send(&(100 / n).to_string())
+        });
+
+        assert_eq!(get(&app, "/divide/4"), "200 OK 25");
+        assert_eq!(get(&app, "/divide/0"), "500 Internal Server Error Internal Server Error");
+        // The route is not poisoned by the panic
+        assert_eq!(get(&app, "/divide/5"), "200 OK 20");
+
+        app.on_panic(|req, res| res.send(&format!("Oops on {}", req.get_route().unwrap())));
+        assert_eq!(get(&app, "/divide/0"), "500 Internal Server Error Oops on /divide/:n");
+    }
+
+    #[test]
+    fn panics_keep_layer_headers() {
+        struct RequestId;
+
+        impl Middleware for RequestId {
+            fn handle(&self, req: &mut Request, res: &mut Response, next: Next) {
+                res.set_header("X-Request-Id", "42");
+                next.run(req, res);
+            }
+        }
+
+        let mut app = App::new("127.0.0.1", 4221);
+        app.layer(RequestId);
+        let admin = app.router("admin");
+        admin.layer(RequestId);
+        admin.get("/", |_req, res| {
+            res.set_header("Content-Type", "text/html");
+            panic!("boom")
+        });
+
+        let mut req = Request::new("GET /admin/ HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let res = app.process(&mut req);
+        assert_eq!(res.get_status(), &HttpStatus::InternalServerError);
+        assert_eq!(res.get_header("X-Request-Id").unwrap(), "42");
+        assert_eq!(res.get_header("Content-Type").unwrap(), "text/plain");
+        assert_eq!(res.get_body().unwrap(), b"Internal Server Error");
+    }
+
+    #[test]
+    fn error_handlers() {
+        let mut app = App::new("127.0.0.1", 4221);
+        app.get("/missing", |_req, res| {
+            res.set_status(HttpStatus::NotFound);
+            res.send("No such item")
+        });
+        assert_eq!(get(&app, "/nowhere"), "404 Not Found Not Found");
+
+        app.not_found(|req, res| res.send(&format!("Nothing at {}", req.get_uri())));
+        assert_eq!(get(&app, "/nowhere"), "404 Not Found Nothing at /nowhere");
+        // Responses built by handlers are left alone
+        assert_eq!(get(&app, "/missing"), "404 Not Found No such item");
+
+        let admin = app.router("admin");
+        admin.layer(BasicAuth::new("admin", |_: &str, _: &str| false));
+        admin.get("/", |_req, res| res.send("Dashboard"));
+        app.on_error(HttpStatus::Unauthorized, |_req, res| res.send("Please log in"));
+        assert_eq!(get(&app, "/admin/"), "401 Unauthorized Please log in");
+    }
+
+    #[test]
+    fn router_fallback() {
+        let mut app = App::new("127.0.0.1", 4221);
+        app.not_found(|_req, res| res.send("Site not found"));
+        let api = app.router("api");
+        api.get("/users", |_req, res| res.send("Users"));
+        api.fallback(|req, res| res.send(&format!("No endpoint {}", req.get_uri())));
+
+        assert_eq!(get(&app, "/api/users"), "200 OK Users");
+        assert_eq!(get(&app, "/api/orders"), "404 Not Found No endpoint /api/orders");
+        assert_eq!(get(&app, "/apiary"), "404 Not Found Site not found");
+        assert_eq!(get(&app, "/"), "404 Not Found Site not found");
+    }
+
+    #[test]
+    fn fallible_handlers() {
+        let mut app = App::new("127.0.0.1", 4221);
+        app.get("/square/:n", handler(|req, _res| -> Result<String, Error> {
+            let n: i64 = req.get_param("n").ok_or(Error::new(HttpStatus::BadRequest))?.parse()?;
+            match n {
+                0 => Err(Error::not_found("Zero has no square here")),
+                _ => Ok((n * n).to_string()),
+            }
+        }));
+        app.on_error(HttpStatus::Forbidden, |_req, res| res.send("Go away"));
+        app.get("/secret", handler(|_req, _res| Err::<(), _>(HttpStatus::Forbidden)));
+
+        assert_eq!(get(&app, "/square/7"), "200 OK 49");
+        assert_eq!(get(&app, "/square/x"), "400 Bad Request Invalid number: invalid digit found in string");
+        assert_eq!(get(&app, "/square/0"), "404 Not Found Zero has no square here");
+        assert_eq!(get(&app, "/secret"), "403 Forbidden Go away");
+        // Errors with a message go through the error handlers too
+        app.not_found(|_req, res| {
+            let detail = res.get_error_detail().unwrap_or("no detail").to_string();
+            res.send(&format!("Not here: {}", detail))
+        });
+        assert_eq!(get(&app, "/square/0"), "404 Not Found Not here: Zero has no square here");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn problem_details() {
+        let mut app = App::new("127.0.0.1", 4221);
+        app.get("/users", |_req, res| res.send("Users"));
+        app.problem_details(true);
+
+        let mut req = Request::new("POST /users HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let res = app.process(&mut req);
+        assert_eq!(res.get_status(), &HttpStatus::MethodNotAllowed);
+        assert_eq!(res.get_header("Allow").unwrap(), "GET");
+        assert_eq!(res.get_header("Content-Type").unwrap(), "application/problem+json");
+        let body: serde_json::Value = serde_json::from_slice(res.get_body().unwrap()).unwrap();
+        assert_eq!(body, serde_json::json!({
+            "type": "about:blank",
+            "title": "Method Not Allowed",
+            "status": 405,
+            "instance": "/users",
+        }));
+
+        // Messages of the errors returned by handlers become the detail
+        app.get("/users/:id", handler(|_req, _res| Err::<(), _>(Error::not_found("No user 7"))));
+        let mut req = Request::new("GET /users/7 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let res = app.process(&mut req);
+        let body: serde_json::Value = serde_json::from_slice(res.get_body().unwrap()).unwrap();
+        assert_eq!(body["detail"], "No user 7");
+
+        // Custom error handlers win
+        app.not_found(|_req, res| res.send("Nothing here"));
+        assert_eq!(get(&app, "/nowhere"), "404 Not Found Nothing here");
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let mut app = App::new("127.0.0.1", 4221);
+        app.get("/", |_req, res| res.send("Home"));
+        // The layers of the router wrap its 405 responses
+        let admin = app.router("admin");
+        admin.layer(BasicAuth::new("admin", |_: &str, _: &str| false));
+        admin.get("/users", |_req, res| res.send("Users"));
+        let post = |uri: &str| app.process(&mut Request::new(&format!("POST {} HTTP/1.1\r\n\r\n", uri)).unwrap());
+
+        let res = post("/");
+        assert_eq!(res.get_status(), &HttpStatus::MethodNotAllowed);
+        assert_eq!(res.get_header("Allow").unwrap(), "GET");
+        // Routes only match the whole path
+        let res = post("/nope");
+        assert_eq!(res.get_status(), &HttpStatus::NotFound);
+        assert!(res.get_header("Allow").is_none());
+        assert_eq!(get(&app, "/nope"), "404 Not Found Not Found");
+        assert_eq!(get(&app, "/?page=2"), "200 OK Home");
+        let res = post("/admin/users");
+        assert_eq!(res.get_status(), &HttpStatus::Unauthorized);
+        assert!(res.get_header("Allow").is_none());
+    }
+
+    #[test]
+    fn route_timeout() {
+        use std::time::Duration;
+        use crate::middleware::timeout::Timeout;
+
+        let mut app = App::new("127.0.0.1", 4221);
+        app.layer(Timeout::new(Duration::from_millis(20)));
+        let slow = |_req| async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            "Finally"
+        };
+        app.get("/slow", async_handler(slow));
+        app.get("/patient", async_handler(slow))
+            .layer(Timeout::new(Duration::from_secs(5)).status(HttpStatus::GatewayTimeout));
+
+        assert_eq!(get(&app, "/slow"), "503 Service Unavailable Service Unavailable");
+        // The route layer replaces the app deadline
+        assert_eq!(get(&app, "/patient"), "200 OK Finally");
+    }
+
+    #[test]
+    fn concurrent_async_handlers() {
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+        use tokio::runtime::Builder;
+        use crate::middleware::timeout::Timeout;
+
+        let mut app = App::new("127.0.0.1", 4221);
+        app.get("/slow", async_handler(|_req| async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            "Finally"
+        })).layer(Timeout::new(Duration::from_millis(300)));
+
+        // Requests to the same route run side by side, each within its own deadline
+        let start = Instant::now();
+        std::thread::scope(|scope| {
+            let requests: Vec<_> = (0..2).map(|_| scope.spawn(|| get(&app, "/slow"))).collect();
+            for request in requests {
+                assert_eq!(request.join().unwrap(), "200 OK Finally");
+            }
+        });
+        assert!(start.elapsed() < Duration::from_millis(400));
+
+        // Also when called from the workers of a runtime
+        let app = Arc::new(app);
+        let runtimes = [
+            Builder::new_multi_thread().enable_all().build().unwrap(),
+            Builder::new_current_thread().enable_all().build().unwrap(),
+        ];
+        for runtime in runtimes {
+            let app = app.clone();
+            let body = runtime.block_on(runtime.spawn(async move { get(&app, "/slow") })).unwrap();
+            assert_eq!(body, "200 OK Finally");
+        }
+    }
+
+    #[test]
+    fn trusted_proxies() {
+        let mut app = App::new("127.0.0.1", 4221);
+        app.trusted_proxies(&["10.0.0.0/8"], ProxyHeader::XForwarded).unwrap();
+        app.get("/whoami", |req, res| {
+            res.send(&format!("{} {}://{}", req.remote_addr().unwrap().ip(), req.scheme(), req.host().unwrap()))
+        });
+
+        let mut req = Request::new("GET /whoami HTTP/1.1\r\nHost: backend\r\nX-Forwarded-For: 203.0.113.7\r\nX-Forwarded-Proto: https\r\nX-Forwarded-Host: example.com\r\n\r\n").unwrap();
+        req.set_peer_addr("10.1.1.1:40000".parse().unwrap());
+        let res = app.process(&mut req);
+        assert_eq!(res.get_body().unwrap(), b"203.0.113.7 https://example.com");
+    }
+
+    #[test]
+    fn extractors() {
+        #[derive(serde::Deserialize)]
+        struct Paging { page: u32 }
+
+        let mut app = App::new("127.0.0.1", 4221);
+        app.get("/users/:id/posts", extract(|Path(id): Path<u32>, Query(paging): Query<Paging>| {
+            format!("Posts of {}, page {}", id, paging.page)
+        }));
+        app.get("/users/:id", extract(|Path(id): Path<u32>| format!("User {}", id)));
+
+        assert_eq!(get(&app, "/users/7/posts?page=2"), "200 OK Posts of 7, page 2");
+        // The query string is not part of the last parameter
+        assert_eq!(get(&app, "/users/7?tab=posts"), "200 OK User 7");
+        assert_eq!(get(&app, "/users/7/posts?page=last"), "400 Bad Request Invalid query string: invalid digit found in string");
+    }
+
+    #[test]
+    fn shared_state() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        struct Greeting(&'static str);
+        struct Visits(AtomicUsize);
+
+        // Layers see the state too
+        struct CountVisits;
+        impl Middleware for CountVisits {
+            fn handle(&self, req: &mut Request, res: &mut Response, next: Next) {
+                req.state::<Visits>().unwrap().0.fetch_add(1, Ordering::SeqCst);
+                next.run(req, res);
+            }
+        }
+
+        let mut app = App::new("127.0.0.1", 4221);
+        app.with_state(Greeting("Hello")).with_state(Visits(AtomicUsize::new(0)));
+        app.layer(CountVisits);
+        app.get("/greet", extract(|State(greeting): State<Greeting>, State(visits): State<Visits>| {
+            format!("{} #{}", greeting.0, visits.0.load(Ordering::SeqCst))
+        }));
+        let es = app.router("es");
+        es.with_state(Greeting("Hola"));
+        es.get("/greet", extract(|State(greeting): State<Greeting>| greeting.0));
+
+        assert_eq!(get(&app, "/greet"), "200 OK Hello #1");
+        assert_eq!(get(&app, "/greet"), "200 OK Hello #2");
+        // The state of the router replaces the one of the app
+        assert_eq!(get(&app, "/es/greet"), "200 OK Hola");
+    }
+
+    #[test]
+    fn content_negotiation() {
+        let mut app = App::new("127.0.0.1", 4221);
+        app.get("/user", |req, res| {
+            res.format(req, &[
+                ("application/json", &|res| res.json(r#"{"name":"Ana"}"#)),
+                ("text/html", &|res| res.send("<p>Ana</p>")),
+            ])
+        });
+        let accept = |accept: &str| {
+            let mut req = Request::new(&format!("GET /user HTTP/1.1\r\nAccept: {}\r\n\r\n", accept)).unwrap();
+            let res = app.process(&mut req);
+            assert_eq!(res.get_header("Vary").unwrap(), "Accept");
+            format!("{} {}", res.get_status(), String::from_utf8_lossy(res.get_body().unwrap()))
+        };
+
+        assert_eq!(accept("text/html;q=0.9, application/json"), r#"200 OK {"name":"Ana"}"#);
+        assert_eq!(accept("text/*, application/json;q=0.5"), "200 OK <p>Ana</p>");
+        assert_eq!(accept("application/json;q=0, */*"), "200 OK <p>Ana</p>");
+        assert_eq!(accept("image/png"), "406 Not Acceptable Not Acceptable");
+        // Without preference the first format is used
+        assert_eq!(get(&app, "/user"), r#"200 OK {"name":"Ana"}"#);
+    }
+}
@@ -0,0 +1,182 @@
+// HAProxy PROXY protocol preamble, versions 1 (text) and 2 (binary)
+
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+const V2_HEADER_LENGTH: usize = 16;
+
+/// Addresses of the original connection, as seen by the load balancer.
+/// They are `None` for health checks (`LOCAL`, `UNKNOWN`) and non-IP connections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ProxyHeader {
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Preamble {
+    // More bytes are needed
+    Incomplete,
+    // The header and its length in bytes
+    Complete(ProxyHeader, usize),
+}
+
+fn malformed(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("Malformed PROXY protocol header: {}", reason))
+}
+
+const LOCAL: ProxyHeader = ProxyHeader { source: None, destination: None };
+
+/// Parse the preamble at the start of the connection data
+pub(crate) fn parse(data: &[u8]) -> Result<Preamble, Error> {
+    let prefix_of = |expected: &[u8]| data.len() < expected.len() && expected.starts_with(data);
+    if data.starts_with(&V2_SIGNATURE) {
+        parse_v2(data)
+    } else if data.starts_with(V1_PREFIX) {
+        parse_v1(data)
+    } else if prefix_of(&V2_SIGNATURE) || prefix_of(V1_PREFIX) {
+        Ok(Preamble::Incomplete)
+    } else {
+        Err(malformed("missing signature"))
+    }
+}
+
+// PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n
+fn parse_v1(data: &[u8]) -> Result<Preamble, Error> {
+    let end = match data.windows(2).position(|window| window == b"\r\n") {
+        Some(end) if end + 2 <= V1_MAX_LENGTH => end,
+        Some(_) => return Err(malformed("line too long")),
+        None if data.len() >= V1_MAX_LENGTH => return Err(malformed("line too long")),
+        None => return Ok(Preamble::Incomplete),
+    };
+    let line = std::str::from_utf8(&data[V1_PREFIX.len()..end]).map_err(|_| malformed("not ASCII"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+
+    let header = match fields.as_slice() {
+        ["UNKNOWN", ..] => LOCAL,
+        [family @ ("TCP4" | "TCP6"), source, destination, source_port, destination_port] => {
+            let ip = |value: &str| -> Result<IpAddr, Error> {
+                let ip: IpAddr = value.parse().map_err(|_| malformed("invalid address"))?;
+                match (ip, *family) {
+                    (IpAddr::V4(_), "TCP4") | (IpAddr::V6(_), "TCP6") => Ok(ip),
+                    _ => Err(malformed("address doesn't match the protocol")),
+                }
+            };
+            let port = |value: &str| -> Result<u16, Error> {
+                // Ports are plain decimal numbers without leading zeros
+                if value.starts_with('0') && value.len() > 1 {
+                    return Err(malformed("invalid port"));
+                }
+                value.parse().map_err(|_| malformed("invalid port"))
+            };
+            ProxyHeader {
+                source: Some(SocketAddr::new(ip(source)?, port(source_port)?)),
+                destination: Some(SocketAddr::new(ip(destination)?, port(destination_port)?)),
+            }
+        },
+        _ => return Err(malformed("unknown protocol or missing fields")),
+    };
+    Ok(Preamble::Complete(header, end + 2))
+}
+
+fn parse_v2(data: &[u8]) -> Result<Preamble, Error> {
+    if data.len() < V2_HEADER_LENGTH {
+        return Ok(Preamble::Incomplete);
+    }
+    let version = data[12] >> 4;
+    let command = data[12] & 0x0F;
+    let family = data[13];
+    let length = u16::from_be_bytes([data[14], data[15]]) as usize;
+    if version != 2 {
+        return Err(malformed("unsupported version"));
+    }
+    if data.len() < V2_HEADER_LENGTH + length {
+        return Ok(Preamble::Incomplete);
+    }
+    let addresses = &data[V2_HEADER_LENGTH..V2_HEADER_LENGTH + length];
+    let port = |at: usize| u16::from_be_bytes([addresses[at], addresses[at + 1]]);
+
+    let header = match (command, family >> 4) {
+        // LOCAL: health check of the load balancer itself
+        (0x0, _) => LOCAL,
+        // PROXY over IPv4
+        (0x1, 0x1) => {
+            if length < 12 {
+                return Err(malformed("address block too short"));
+            }
+            let ip = |at: usize| IpAddr::V4(Ipv4Addr::new(addresses[at], addresses[at + 1], addresses[at + 2], addresses[at + 3]));
+            ProxyHeader {
+                source: Some(SocketAddr::new(ip(0), port(8))),
+                destination: Some(SocketAddr::new(ip(4), port(10))),
+            }
+        },
+        // PROXY over IPv6
+        (0x1, 0x2) => {
+            if length < 36 {
+                return Err(malformed("address block too short"));
+            }
+            let ip = |at: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&addresses[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            ProxyHeader {
+                source: Some(SocketAddr::new(ip(0), port(32))),
+                destination: Some(SocketAddr::new(ip(16), port(34))),
+            }
+        },
+        // PROXY over unix sockets or an unspecified family: keep the socket addresses
+        (0x1, 0x0 | 0x3) => LOCAL,
+        (0x1, _) => return Err(malformed("unknown address family")),
+        _ => return Err(malformed("unknown command")),
+    };
+    Ok(Preamble::Complete(header, V2_HEADER_LENGTH + length))
+}
+
+
+#[cfg(test)]
+mod test_proxy_protocol {
+    use super::{parse, Preamble, ProxyHeader, V2_SIGNATURE};
+
+    fn header(source: &str, destination: &str) -> ProxyHeader {
+        ProxyHeader { source: Some(source.parse().unwrap()), destination: Some(destination.parse().unwrap()) }
+    }
+
+    #[test]
+    fn version_1() {
+        let data = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n";
+        assert_eq!(parse(data).unwrap(), Preamble::Complete(header("192.0.2.1:56324", "198.51.100.1:443"), 45));
+
+        let data = b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 80\r\n";
+        assert_eq!(parse(data).unwrap(), Preamble::Complete(header("[2001:db8::1]:4000", "[2001:db8::2]:80"), data.len()));
+
+        assert_eq!(parse(b"PROXY TCP4 192.0").unwrap(), Preamble::Incomplete);
+        assert_eq!(parse(b"PRO").unwrap(), Preamble::Incomplete);
+        assert!(parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 99999 443\r\n").is_err());
+        assert!(parse(b"PROXY TCP4 2001:db8::1 198.51.100.1 1 443\r\n").is_err());
+        assert!(parse(b"GET / HTTP/1.1\r\n").is_err());
+    }
+
+    #[test]
+    fn version_2() {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend([0x21, 0x11, 0x00, 0x0C]);
+        data.extend([192, 0, 2, 1, 198, 51, 100, 1]);
+        data.extend(56324u16.to_be_bytes());
+        data.extend(443u16.to_be_bytes());
+        assert_eq!(parse(&data[..20]).unwrap(), Preamble::Incomplete);
+        data.extend(b"GET / HTTP/1.1\r\n");
+        assert_eq!(parse(&data).unwrap(), Preamble::Complete(header("192.0.2.1:56324", "198.51.100.1:443"), 28));
+
+        // LOCAL command of a health check
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend([0x20, 0x00, 0x00, 0x00]);
+        assert!(matches!(parse(&local).unwrap(), Preamble::Complete(ProxyHeader { source: None, .. }, 16)));
+
+        data[12] = 0x31;
+        assert!(parse(&data).is_err());
+    }
+}
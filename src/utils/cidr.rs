@@ -0,0 +1,103 @@
+// IPv4 and IPv6 network ranges in CIDR notation
+
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A network range like `10.0.0.0/8` or `fd00::/8`.
+/// A plain address is a range with a single address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+fn invalid(value: &str) -> Error {
+    Error::new(ErrorKind::InvalidInput, format!("Invalid CIDR range: {}", value))
+}
+
+// Address as a 128 bit number, IPv4 addresses use the low 32 bits
+fn bits(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip) as u128,
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+fn width(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+fn mask(width: u8, prefix: u8) -> u128 {
+    match prefix {
+        0 => 0,
+        _ => (u128::MAX << (width - prefix)) & (u128::MAX >> (128 - width as u32)),
+    }
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual stack socket show up as ::ffff:a.b.c.d
+        let ip = ip.to_canonical();
+        if width(ip) != width(self.network) {
+            return false;
+        }
+        let mask = mask(width(ip), self.prefix);
+        bits(ip) & mask == bits(self.network) & mask
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Error> {
+        let (address, prefix) = match value.trim().split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value.trim(), None),
+        };
+        let network = IpAddr::from_str(address).map_err(|_| invalid(value))?.to_canonical();
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid(value))?,
+            None => width(network),
+        };
+        if prefix > width(network) {
+            return Err(invalid(value));
+        }
+        Ok(Cidr { network, prefix })
+    }
+}
+
+// Parse a list of ranges, failing on the first invalid one
+pub fn parse_list(ranges: &[&str]) -> Result<Vec<Cidr>, Error> {
+    ranges.iter().map(|range| range.parse()).collect()
+}
+
+
+#[cfg(test)]
+mod test_cidr {
+    use super::Cidr;
+
+    #[test]
+    fn contains() {
+        let private: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(private.contains("10.20.30.40".parse().unwrap()));
+        assert!(private.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!private.contains("11.0.0.1".parse().unwrap()));
+        assert!(!private.contains("::1".parse().unwrap()));
+
+        let local: Cidr = "fd00::/8".parse().unwrap();
+        assert!(local.contains("fd12:3456::1".parse().unwrap()));
+        assert!(!local.contains("fe80::1".parse().unwrap()));
+
+        let host: Cidr = "192.168.1.7".parse().unwrap();
+        assert!(host.contains("192.168.1.7".parse().unwrap()));
+        assert!(!host.contains("192.168.1.8".parse().unwrap()));
+
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains("8.8.8.8".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("localhost".parse::<Cidr>().is_err());
+    }
+}
@@ -3,7 +3,9 @@ use std::fs;
 use std::io::Error;
 use std::path::Path;
 
-pub fn format_html(file_path: &str, params: HashMap<String, String>) -> Result<String, Error> {
+// Globals are set by middleware (e.g. the CSRF token) and, unlike params,
+// don't need to appear in the template. Messages like {{t "key"}} are replaced by `translate`.
+pub fn format_html(file_path: &str, params: HashMap<String, String>, globals: &HashMap<String, String>, translate: &dyn Fn(&str) -> String) -> Result<String, Error> {
     let file_string = format!("./tests/templates/{}", file_path);
     let file= Path::new(&file_string);
 
@@ -22,21 +24,45 @@ pub fn format_html(file_path: &str, params: HashMap<String, String>) -> Result<S
             false => old_content = content.clone(),
         }
     }
+    for (var, val) in globals {
+        content = content.replace(&format!("{{{{{}}}}}", var), val);
+    }
+
+    Ok(translate_messages(&content, translate))
+}
 
-    Ok(content)
+// Replace the {{t "key"}} messages of a template
+fn translate_messages(content: &str, translate: &dyn Fn(&str) -> String) -> String {
+    let mut translated = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{t \"") {
+        let after = &rest[start + 5..];
+        match after.find("\"}}") {
+            Some(end) => {
+                translated.push_str(&rest[..start]);
+                translated.push_str(&translate(&after[..end]));
+                rest = &after[end + 3..];
+            },
+            None => break,
+        }
+    }
+    translated.push_str(rest);
+    translated
 }
 
 pub fn format_regex(path: &str, params: &mut Vec<String>) -> String {
     let mut regex = String::new();
-    regex.push_str("^");
-    for (_, part) in path.split('/').enumerate() {
-        if part.starts_with(':') {
-            regex.push_str("/([^/]+)");
-            params.push(part[1..].to_string());
-        } else if part != "" {
-            regex.push_str("/");
-            regex.push_str(part);
+    regex.push('^');
+    for part in path.split('/') {
+        if let Some(param) = part.strip_prefix(':') {
+            regex.push_str("/([^/?]+)");
+            params.push(param.to_string());
+        } else if !part.is_empty() {
+            regex.push('/');
+            regex.push_str(&regex::escape(part));
         }
     }
+    // The whole path must match, with an optional trailing slash and query string
+    regex.push_str(r"/?(?:\?.*)?$");
     regex
 }
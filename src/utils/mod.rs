@@ -1,2 +1,4 @@
 pub(crate) mod parsers;
-pub(crate) mod formatter; 
+pub(crate) mod formatter;
+pub(crate) mod random;
+pub(crate) mod cidr;
@@ -1,34 +1,122 @@
 extern crate httparse;
 extern crate regex;
 
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use std::collections::HashMap;
 
 use crate::http::methods::HttpMethod;
 use crate::http::request::Request;
 
+// Decode %XX escapes and '+' (space) of a form or query string component
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    },
+                    // Malformed escapes are kept as they are
+                    None => decoded.push(b'%'),
+                }
+            },
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+// Length of the body announced in the head of a request, 0 when there is none
+pub fn content_length(head: &[u8]) -> Result<usize, Error> {
+    let head = String::from_utf8_lossy(head);
+    for line in head.split("\r\n").skip(1) {
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                return value.trim().parse().map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid Content-Length"));
+            }
+        }
+    }
+    Ok(0)
+}
+
+// Media ranges (or language tags) of an Accept header with their weight, lowercase and without other parameters.
+// Weights that are not valid count as 1.
+pub fn parse_accept(header: &str) -> Vec<(String, f32)> {
+    header.split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let media = parts.next()?.trim().to_ascii_lowercase();
+            if media.is_empty() {
+                return None;
+            }
+            let weight = parts
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .and_then(|(_, value)| value.trim().parse::<f32>().ok())
+                .map(|q| q.clamp(0.0, 1.0))
+                .unwrap_or(1.0);
+            Some((media, weight))
+        })
+        .collect()
+}
+
+// Parse an application/x-www-form-urlencoded string into its decoded pairs
+pub fn parse_urlencoded(input: &str) -> Vec<(String, String)> {
+    input.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (percent_decode(name), percent_decode(value)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
 #[allow(dead_code)]
-pub fn parse_request<'a>(request: String, my_req: &'a mut Request) -> Result<(), Error> {
+pub fn parse_request(request: &[u8], my_req: &mut Request) -> Result<(), Error> {
 
     let mut headers = [httparse::EMPTY_HEADER; 16];
     let mut req = httparse::Request::new(&mut headers);
-    let res = req.parse(request.as_bytes());
+    let res = req.parse(request);
 
     // Check if the request is valid
     match res {
-        Ok(_) => {
+        Ok(status) => {
             let method = HttpMethod::from_str(req.method.unwrap());
             let path = req.path.unwrap();
-            let mut headers = HashMap::new();
-            // Insert headers into a HashMap
+            let mut headers: HashMap<String, String> = HashMap::new();
+            // Insert headers into a HashMap, joining repeated ones (whatever their case)
+            // in order, so that no line of a list like X-Forwarded-For is lost
             for header in &mut *req.headers {
-                headers.insert(header.name.to_string(), header.value.len() as u8);
+                let value = String::from_utf8_lossy(header.value).to_string();
+                let existing = headers.iter_mut().find(|(name, _)| name.eq_ignore_ascii_case(header.name));
+                match existing {
+                    Some((name, joined)) => {
+                        let separator = if name.eq_ignore_ascii_case("Cookie") { "; " } else { ", " };
+                        joined.push_str(separator);
+                        joined.push_str(&value);
+                    },
+                    None => {
+                        headers.insert(header.name.to_string(), value);
+                    },
+                }
             }
 
-            // Get the body of the request
-            let body_start = req.headers.len() + 1;
-            let body = &request.split("\r\n").nth(body_start).unwrap_or_else(|| "Error reading body");
-            my_req.set_body(body.as_bytes().to_vec());
+            // Get the body of the request, everything after the blank line
+            let body_start = match status {
+                httparse::Status::Complete(len) => len,
+                httparse::Status::Partial => request.len(),
+            };
+            my_req.set_body(request[body_start..].to_vec());
 
             
             // Set the request data
@@ -39,6 +127,6 @@ pub fn parse_request<'a>(request: String, my_req: &'a mut Request) -> Result<(),
 
             Ok(())        
         },
-        Err(err) => Err(Error::new(std::io::ErrorKind::Other, err))
+        Err(err) => Err(Error::other(err))
     }
 }
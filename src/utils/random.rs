@@ -0,0 +1,9 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+
+// Random url-safe token with `bytes` bytes of entropy, from the OS generator
+pub fn token(bytes: usize) -> String {
+    let mut buffer = vec![0u8; bytes];
+    getrandom::getrandom(&mut buffer).expect("Error reading random bytes");
+    URL_SAFE_NO_PAD.encode(buffer)
+}
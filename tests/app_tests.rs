@@ -3,8 +3,7 @@
 mod response_test {
     extern crate myapi_rust;
     
-    use myapi_rust::{App, Response};
-    use reqwest;
+    use myapi_rust::{App, Cookie, Response};
 
     // Response tests
 
@@ -25,6 +24,57 @@ mod response_test {
         assert_eq!(body, r#"{"message": "Hello, World!"}"#.as_bytes());
     }
 
+    #[test]
+    fn response_set_cookie() {
+        let mut res = Response::empty();
+        res.set_cookie(Cookie::new("theme", "dark").path("/"));
+        res.set_cookie(Cookie::new("lang", "ca").http_only(true));
+        res.remove_cookie("session");
+        let cookies = res.get_header_values("Set-Cookie");
+        assert_eq!(cookies.len(), 3);
+        assert_eq!(cookies[0], "theme=dark; Path=/");
+        assert_eq!(cookies[1], "lang=ca; HttpOnly");
+        assert_eq!(cookies[2], "session=; Path=/; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0");
+        // Every value is serialized as its own header line
+        assert_eq!(res.to_string().matches("Set-Cookie: ").count(), 3);
+    }
+
+    #[test]
+    fn response_to_bytes() {
+        let image = std::fs::read("tests/files/image.png").unwrap();
+        let mut res = Response::empty();
+        res.set_status(myapi_rust::HttpStatus::Ok);
+        res.send_file("image.png");
+        res.set_header("Content-Length", "1");
+        let bytes = res.to_bytes();
+
+        let head_end = bytes.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8(bytes[..head_end].to_vec()).unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("\r\nDate: "));
+        // The length of the body replaces the one set by the handler
+        assert_eq!(head.matches("Content-Length: ").count(), 1);
+        assert!(head.contains(&format!("Content-Length: {}\r\n", image.len())));
+        // The body is sent byte for byte
+        assert_eq!(&bytes[head_end..], image.as_slice());
+    }
+
+    #[test]
+    fn bodiless_response_to_bytes() {
+        use myapi_rust::HttpStatus;
+
+        for status in [HttpStatus::NoContent, HttpStatus::NotModified] {
+            let mut res = Response::empty();
+            res.send("ignored");
+            res.set_status(status);
+            res.set_header("Content-Length", "7");
+            let bytes = String::from_utf8(res.to_bytes()).unwrap();
+            assert!(bytes.starts_with(&format!("HTTP/1.1 {}\r\n", status)));
+            assert!(!bytes.contains("Content-Length"));
+            assert!(bytes.ends_with("\r\n\r\n"));
+        }
+    }
+
     #[test]
     #[ignore]
     fn response_render_template() {
@@ -37,6 +87,54 @@ mod response_test {
         assert_eq!(body, "Hello, John!".as_bytes());
     }
 
+    // Request tests
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn request_json() {
+        use myapi_rust::{HttpStatus, Request};
+
+        #[derive(serde::Deserialize, serde::Serialize, Debug, PartialEq)]
+        struct User { name: String, age: u8 }
+
+        let req = Request::new("POST /users HTTP/1.1\r\nContent-Type: application/json; charset=utf-8\r\n\r\n{\"name\": \"Ana\", \"age\": 30}").unwrap();
+        let user: User = req.json().unwrap();
+        assert_eq!(user, User { name: "Ana".to_string(), age: 30 });
+
+        let mut res = Response::empty();
+        res.json_value(&user);
+        assert_eq!(res.get_header("Content-Type").unwrap(), "application/json");
+        assert_eq!(res.get_body().unwrap(), br#"{"name":"Ana","age":30}"#);
+
+        let req = Request::new("POST /users HTTP/1.1\r\nContent-Type: text/plain\r\n\r\nAna").unwrap();
+        assert_eq!(req.json::<User>().unwrap_err().get_status(), HttpStatus::UnsupportedMediaType);
+
+        let req = Request::new("POST /users HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{\"name\": \"Ana\"}").unwrap();
+        assert_eq!(req.json::<User>().unwrap_err().get_status(), HttpStatus::BadRequest);
+    }
+
+    #[test]
+    fn request_form() {
+        use myapi_rust::{HttpStatus, Request};
+
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Search { q: String, page: u32 }
+
+        let req = Request::new("POST /search HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nq=caf%C3%A9+con+llet&page=2&tag=a&tag=b%26c").unwrap();
+        let form = req.form().unwrap();
+        assert_eq!(form.get("q"), Some("café con llet"));
+        assert_eq!(form.get_all("tag"), vec!["a", "b&c"]);
+        assert_eq!(req.form_as::<Search>().unwrap(), Search { q: "café con llet".to_string(), page: 2 });
+
+        assert_eq!(req.form_with_limit(10).unwrap_err().get_status(), HttpStatus::PayloadTooLarge);
+
+        let req = Request::new("POST /search HTTP/1.1\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\nq=tea&page=first").unwrap();
+        assert_eq!(req.form_as::<Search>().unwrap_err().get_status(), HttpStatus::BadRequest);
+
+        let req = Request::new("POST /search HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{}").unwrap();
+        assert_eq!(req.form().unwrap_err().get_status(), HttpStatus::UnsupportedMediaType);
+    }
+
     // App tests
 
     #[tokio::test]